@@ -1,15 +1,95 @@
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+use log::{debug, trace};
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+use serde::de::{DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
 use serde_derive::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BTreeMap, BinaryHeap, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufReader;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::OnceLock;
+use std::time::SystemTime;
 use std::usize;
 use walkdir::WalkDir;
 
+#[derive(Debug)]
+pub enum LibError {
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    PackExists(String),
+    PackNotFound(String),
+    // add_file found no pack whose folder holds the path
+    NoPackForPath(String),
+    // Loading one of several files failed
+    Shard {
+        path: String,
+        source: Box<LibError>,
+    },
+    #[cfg(feature = "audio")]
+    Audio(hound::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl fmt::Display for LibError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LibError::Io(e) => write!(f, "io error: {}", e),
+            LibError::Json(e) => write!(f, "json error: {}", e),
+            LibError::PackExists(name) => write!(f, "pack \"{}\" already exists", name),
+            LibError::PackNotFound(name) => write!(f, "pack \"{}\" not found", name),
+            LibError::NoPackForPath(path) => write!(f, "no pack holds \"{}\"", path),
+            LibError::Shard { path, source } => write!(f, "{}: {}", path, source),
+            #[cfg(feature = "audio")]
+            LibError::Audio(e) => write!(f, "audio error: {}", e),
+            #[cfg(feature = "sqlite")]
+            LibError::Sqlite(e) => write!(f, "sqlite error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LibError {}
+
+impl From<std::io::Error> for LibError {
+    fn from(e: std::io::Error) -> Self {
+        LibError::Io(e)
+    }
+}
+
+impl From<serde_json::Error> for LibError {
+    fn from(e: serde_json::Error) -> Self {
+        LibError::Json(e)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl From<hound::Error> for LibError {
+    fn from(e: hound::Error) -> Self {
+        LibError::Audio(e)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for LibError {
+    fn from(e: rusqlite::Error) -> Self {
+        LibError::Sqlite(e)
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct SearchResult {
     pub samples: Vec<Sample>,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SearchParams {
     pub query: String,
     pub sample_type: Option<SampleType>,
@@ -17,6 +97,127 @@ pub struct SearchParams {
     pub min_tempo: Option<u32>,
     pub pack_id: Option<String>,
     pub max_results: Option<i32>,
+    // Loops within tempo_tolerance (default 0) of target_tempo match, closer ones rank higher
+    #[serde(default)]
+    pub target_tempo: Option<u32>,
+    #[serde(default)]
+    pub tempo_tolerance: Option<u32>,
+    // Also accept loops near half or double the target tempo
+    #[serde(default)]
+    pub half_double_time: Option<bool>,
+    #[serde(default)]
+    pub bars: Option<u32>,
+    // Loops matching any of these tempos, tempo_tolerance and half_double_time apply too
+    #[serde(default)]
+    pub tempos: Option<Vec<u32>>,
+    // Number of ranked matches to skip before max_results are taken
+    #[serde(default)]
+    pub offset: Option<usize>,
+    // Parse query with AND, OR, NOT and parentheses, see parse_query_expr.
+    // A malformed expression matches nothing.
+    #[serde(default)]
+    pub boolean_query: Option<bool>,
+    // Matching samples gain usage_count * usage_weight relevance
+    #[serde(default)]
+    pub usage_weight: Option<f32>,
+    // Tokens that aren't substrings of the path may still match fuzzily.
+    // Samples with at least one exact token always rank ahead of fuzzy-only ones.
+    #[serde(default)]
+    pub fuzzy: Option<bool>,
+    // Count every occurrence of a token, the nth one adds occurrence_decay^(n-1).
+    // Unset scores one point per token present.
+    #[serde(default)]
+    pub occurrence_decay: Option<f32>,
+    // Samples match if their type is any of these, the Loop tempo is ignored.
+    // Applies in addition to sample_type when both are set.
+    #[serde(default)]
+    pub sample_types: Option<Vec<SampleType>>,
+    // Matches come back in random order instead of by relevance.
+    // The same random_seed gives the same order on every platform and release,
+    // without one every search differs.
+    #[serde(default)]
+    pub random: Option<bool>,
+    #[serde(default)]
+    pub random_seed: Option<u64>,
+    // No pack contributes more than this many matches, the best of each pack are kept
+    #[serde(default)]
+    pub max_per_pack: Option<usize>,
+    // Only samples drum_category puts in this category
+    #[serde(default)]
+    pub drum_category: Option<DrumCategory>,
+    // Keywords drum_category is checked with, None uses default_drum_keywords
+    #[serde(default)]
+    pub drum_keywords: Option<Vec<(DrumCategory, Vec<String>)>>,
+    // Only samples whose detect_key is this key or an enharmonic spelling of it
+    #[serde(default)]
+    pub key: Option<MusicalKey>,
+    // Only samples in a key that mixes with this one, see MusicalKey::compatible_keys
+    #[serde(default)]
+    pub compatible_key: Option<MusicalKey>,
+    // Weight matched tokens by how rare they are in the library, see SearchIndex.
+    // Ignored by search_lib_file.
+    #[serde(default)]
+    pub tf_idf: Option<bool>,
+    // true keeps only samples is_vocal flags, false leaves them out
+    #[serde(default)]
+    pub vocal: Option<bool>,
+    // Keywords vocal is checked with, None uses DEFAULT_VOCAL_KEYWORDS
+    #[serde(default)]
+    pub vocal_keywords: Option<Vec<String>>,
+    // Score tokens by where they match, unset scores every path match the same
+    #[serde(default)]
+    pub field_weights: Option<FieldWeights>,
+}
+
+// Points a token gets for matching the file name, a folder or a tag, the best field
+// counts once. Tags are only searched with a tag weight above 0.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct FieldWeights {
+    pub name: f32,
+    pub folder: f32,
+    pub tag: f32,
+}
+
+impl Default for FieldWeights {
+    fn default() -> Self {
+        FieldWeights {
+            name: 1.0,
+            folder: 1.0,
+            tag: 0.0,
+        }
+    }
+}
+
+impl FieldWeights {
+    fn tag_hit(&self, sample: &Sample, token: &str) -> bool {
+        self.tag > 0.0
+            && sample
+                .tags
+                .iter()
+                .any(|tag| tag.to_lowercase().contains(token))
+    }
+
+    // Tokens spanning a folder and the file name count as folder hits
+    fn weight_of(&self, sample: &Sample, path_lowercase: &str, token: &str) -> f32 {
+        let file_start = path_lowercase
+            .rfind(['/', '\\'])
+            .map_or(0, |index| index + 1);
+        let (folders, file_name) = path_lowercase.split_at(file_start);
+        let mut weight: f32 = 0.0;
+        if file_name.contains(token) {
+            weight = weight.max(self.name);
+        } else if path_lowercase.contains(token) {
+            weight = weight.max(self.folder);
+        }
+        if folders.contains(token) {
+            weight = weight.max(self.folder);
+        }
+        if self.tag_hit(sample, token) {
+            weight = weight.max(self.tag);
+        }
+        weight
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, PartialOrd, Ord)]
@@ -25,11 +226,431 @@ pub enum SampleType {
     OneShot,
 }
 
-#[derive(Debug, Serialize, Deserialize, Eq, Ord, PartialEq, PartialOrd, Clone)]
+// Stored as a pitch class (C = 0 .. B = 11), so C# and Db are the same value
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "KeyFields")]
+pub struct MusicalKey {
+    pitch_class: u8,
+    minor: bool,
+}
+
+// Deserialized form of MusicalKey, checked before it becomes one
+#[derive(Deserialize)]
+struct KeyFields {
+    pitch_class: u8,
+    minor: bool,
+}
+
+impl TryFrom<KeyFields> for MusicalKey {
+    type Error = ParseKeyError;
+
+    fn try_from(fields: KeyFields) -> Result<Self, Self::Error> {
+        if fields.pitch_class >= 12 {
+            return Err(ParseKeyError(format!("pitch class {}", fields.pitch_class)));
+        }
+        Ok(MusicalKey {
+            pitch_class: fields.pitch_class,
+            minor: fields.minor,
+        })
+    }
+}
+
+const KEY_NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+impl MusicalKey {
+    // Pitch classes of 12 and above wrap around, 12 is C again
+    pub fn new(pitch_class: u8, minor: bool) -> MusicalKey {
+        MusicalKey {
+            pitch_class: pitch_class % 12,
+            minor,
+        }
+    }
+
+    pub fn pitch_class(&self) -> u8 {
+        self.pitch_class
+    }
+
+    pub fn is_minor(&self) -> bool {
+        self.minor
+    }
+
+    pub fn is_enharmonic(&self, other: &MusicalKey) -> bool {
+        self.pitch_class == other.pitch_class && self.minor == other.minor
+    }
+
+    // A minor for C major and the other way around
+    pub fn relative(&self) -> MusicalKey {
+        let offset = if self.minor { 3 } else { 9 };
+        MusicalKey {
+            pitch_class: (self.pitch_class + offset) % 12,
+            minor: !self.minor,
+        }
+    }
+
+    pub fn is_relative(&self, other: &MusicalKey) -> bool {
+        self.relative().is_enharmonic(other)
+    }
+
+    // Camelot wheel position 1-12, a fifth up is one step clockwise. 8A is A minor, 8B C major.
+    fn camelot_number(&self) -> u8 {
+        let offset = if self.minor { 4 } else { 7 };
+        (self.pitch_class * 7 + offset) % 12 + 1
+    }
+
+    pub fn camelot(&self) -> Option<String> {
+        let letter = if self.minor { 'A' } else { 'B' };
+        Some(format!("{}{}", self.camelot_number(), letter))
+    }
+
+    // The key itself, its neighbours on the wheel (a fifth up and down) and its relative key
+    pub fn compatible_keys(&self) -> Vec<MusicalKey> {
+        let fifth = |semitones: u8| MusicalKey {
+            pitch_class: (self.pitch_class + semitones) % 12,
+            minor: self.minor,
+        };
+        vec![*self, fifth(5), fifth(7), self.relative()]
+    }
+
+    pub fn is_compatible(&self, other: &MusicalKey) -> bool {
+        self.compatible_keys()
+            .iter()
+            .any(|key| key.is_enharmonic(other))
+    }
+}
+
+// Canonical spelling uses sharps, "C#" or "C#m"
+impl fmt::Display for MusicalKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = KEY_NAMES[self.pitch_class as usize % 12];
+        write!(f, "{}{}", name, if self.minor { "m" } else { "" })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseKeyError(pub String);
+
+impl fmt::Display for ParseKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid key \"{}\"", self.0)
+    }
+}
+
+impl std::error::Error for ParseKeyError {}
+
+// A root A-G, an optional '#' or 'b' and "", "maj", "major", "m", "min" or "minor"
+impl FromStr for MusicalKey {
+    type Err = ParseKeyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || ParseKeyError(s.to_string());
+        let mut chars = s.trim().chars();
+        let root: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+            Some('C') => 0,
+            Some('D') => 2,
+            Some('E') => 4,
+            Some('F') => 5,
+            Some('G') => 7,
+            Some('A') => 9,
+            Some('B') => 11,
+            _ => return Err(error()),
+        };
+        let mut rest = chars.as_str();
+        let mut pitch_class = root;
+        if let Some(stripped) = rest.strip_prefix(['#', '♯']) {
+            pitch_class += 1;
+            rest = stripped;
+        } else if let Some(stripped) = rest.strip_prefix(['b', '♭']) {
+            pitch_class -= 1;
+            rest = stripped;
+        }
+        let minor = match rest.to_lowercase().as_str() {
+            "" | "maj" | "major" => false,
+            "m" | "min" | "minor" => true,
+            _ => return Err(error()),
+        };
+        Ok(MusicalKey {
+            pitch_class: pitch_class.rem_euclid(12) as u8,
+            minor,
+        })
+    }
+}
+
+// First word of the file name that parses as a key. The root has to be uppercase,
+// so "a_kick" isn't read as A major while "Bass_Am_120" and "Lead_F#.wav" are keyed.
+pub fn detect_key(path: &str) -> Option<MusicalKey> {
+    file_name_of(path)
+        .split(['_', '-', ' ', '.', '(', ')', '[', ']'])
+        .filter(|word| word.starts_with(|c: char| ('A'..='G').contains(&c)))
+        .find_map(|word| word.parse().ok())
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DrumCategory {
+    Kick,
+    Snare,
+    Hat,
+    Clap,
+    Tom,
+    Cymbal,
+    Perc,
+}
+
+// Whole words (or their plural) that put a sample in a category, see drum_category_with
+pub fn default_drum_keywords() -> Vec<(DrumCategory, Vec<String>)> {
+    let keywords = |words: &[&str]| words.iter().map(|word| word.to_string()).collect();
+    vec![
+        (
+            DrumCategory::Kick,
+            keywords(&["kick", "kik", "bd", "bassdrum", "kickdrum"]),
+        ),
+        (
+            DrumCategory::Snare,
+            keywords(&["snare", "snr", "sd", "rim", "rimshot"]),
+        ),
+        (
+            DrumCategory::Hat,
+            keywords(&["hat", "hihat", "hh", "openhat", "closedhat"]),
+        ),
+        (DrumCategory::Clap, keywords(&["clap", "handclap"])),
+        (DrumCategory::Tom, keywords(&["tom", "floortom"])),
+        (
+            DrumCategory::Cymbal,
+            keywords(&["cymbal", "crash", "ride", "splash", "china"]),
+        ),
+        (
+            DrumCategory::Perc,
+            keywords(&[
+                "perc",
+                "percussion",
+                "shaker",
+                "conga",
+                "bongo",
+                "tambourine",
+                "cowbell",
+                "clave",
+                "snap",
+            ]),
+        ),
+    ]
+}
+
+pub const DEFAULT_VOCAL_KEYWORDS: [&str; 5] = ["vocal", "vox", "acapella", "adlib", "phrase"];
+
+pub fn default_vocal_keywords() -> Vec<String> {
+    DEFAULT_VOCAL_KEYWORDS
+        .iter()
+        .map(|word| word.to_string())
+        .collect()
+}
+
+pub fn is_vocal(sample: &Sample) -> bool {
+    is_vocal_with(sample, &default_vocal_keywords())
+}
+
+// Any path word counts, so everything in a "Vocals" folder is vocal too
+pub fn is_vocal_with(sample: &Sample, keywords: &[String]) -> bool {
+    path_tokens(&sample.path)
+        .iter()
+        .any(|token| keywords.iter().any(|word| is_keyword_token(token, word)))
+}
+
+// A lowercased path word matches a keyword exactly or as its plural
+fn is_keyword_token(token: &str, word: &str) -> bool {
+    let word = word.to_lowercase();
+    token == word || token.strip_suffix('s') == Some(word.as_str())
+}
+
+pub fn drum_category(sample: &Sample) -> Option<DrumCategory> {
+    drum_category_with(sample, &default_drum_keywords())
+}
+
+// The file name decides, folders are only looked at when it has no keyword.
+// None when nothing matches or keywords of several categories do.
+pub fn drum_category_with(
+    sample: &Sample,
+    keywords: &[(DrumCategory, Vec<String>)],
+) -> Option<DrumCategory> {
+    let categories_in = |text: &str| -> Vec<DrumCategory> {
+        let tokens = path_tokens(text);
+        keywords
+            .iter()
+            .filter(|(_, words)| {
+                tokens
+                    .iter()
+                    .any(|token| words.iter().any(|word| is_keyword_token(token, word)))
+            })
+            .map(|(category, _)| *category)
+            .collect()
+    };
+    let file_name = file_name_of(&sample.path);
+    let mut categories = categories_in(&file_name);
+    if categories.is_empty() {
+        let folders = &sample.path[..sample.path.len() - file_name.len()];
+        categories = categories_in(folders);
+    }
+    match categories[..] {
+        [category] => Some(category),
+        _ => None,
+    }
+}
+
+impl SampleType {
+    // Loop tempo, None for one-shots and loops whose tempo wasn't detected (0 or less)
+    pub fn tempo(&self) -> Option<u32> {
+        match self {
+            SampleType::Loop(tempo) if *tempo > 0 => Some(*tempo as u32),
+            _ => None,
+        }
+    }
+}
+
+// Compact text form, "loop:120" or "oneshot"
+impl fmt::Display for SampleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SampleType::Loop(tempo) => write!(f, "loop:{}", tempo),
+            SampleType::OneShot => f.write_str("oneshot"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseSampleTypeError(pub String);
+
+impl fmt::Display for ParseSampleTypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid sample type \"{}\", expected \"oneshot\" or \"loop:<tempo>\"",
+            self.0
+        )
+    }
+}
+
+impl std::error::Error for ParseSampleTypeError {}
+
+impl FromStr for SampleType {
+    type Err = ParseSampleTypeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.trim().to_lowercase();
+        if lower == "oneshot" {
+            return Ok(SampleType::OneShot);
+        }
+        lower
+            .strip_prefix("loop:")
+            .and_then(|tempo| tempo.parse().ok())
+            .map(SampleType::Loop)
+            .ok_or_else(|| ParseSampleTypeError(s.to_string()))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Sample {
     pub path: String,
     pub name: String,
     pub sampletype: SampleType,
+    #[serde(default)]
+    pub bars: Option<u32>,
+    // File modification time at scan time
+    #[serde(default)]
+    pub modified: Option<SystemTime>,
+    // Length in seconds, filled when headers are read on load
+    #[serde(default)]
+    pub duration_secs: Option<f32>,
+    // How often the sample was auditioned, see SearchParams::usage_weight
+    #[serde(default)]
+    pub usage_count: Option<u32>,
+    // Set for loops labeled with a range like "120-124" instead of one tempo
+    #[serde(default)]
+    pub tempo_range: Option<(u32, u32)>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    // Length without leading and trailing silence, see LoadOptions::trim_threshold_db
+    #[serde(default)]
+    pub effective_duration_secs: Option<f32>,
+    // Set by apply_type_overrides, reclassify_if_options_changed keeps these types
+    #[serde(default)]
+    pub type_overridden: bool,
+    // Filled with LoadOptions::cache_paths or build_path_cache, never saved
+    #[serde(skip)]
+    pub path_cache: PathCache,
+}
+
+// Lowercased path searches match against instead of lowercasing it per query
+#[derive(Debug, Clone, Default)]
+pub struct PathCache(Option<String>);
+
+// Total order over durations, missing ones sort first
+fn cmp_duration(a: Option<f32>, b: Option<f32>) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        _ => a.is_some().cmp(&b.is_some()),
+    }
+}
+
+// Field by field like a derive, path_cache is left out so samples compare equal
+// whether or not it is filled
+impl Ord for Sample {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.path
+            .cmp(&other.path)
+            .then_with(|| self.name.cmp(&other.name))
+            .then_with(|| self.sampletype.cmp(&other.sampletype))
+            .then_with(|| self.bars.cmp(&other.bars))
+            .then_with(|| self.modified.cmp(&other.modified))
+            .then_with(|| cmp_duration(self.duration_secs, other.duration_secs))
+            .then_with(|| self.usage_count.cmp(&other.usage_count))
+            .then_with(|| self.tempo_range.cmp(&other.tempo_range))
+            .then_with(|| self.tags.cmp(&other.tags))
+            .then_with(|| cmp_duration(self.effective_duration_secs, other.effective_duration_secs))
+            .then_with(|| self.type_overridden.cmp(&other.type_overridden))
+    }
+}
+
+impl PartialOrd for Sample {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Sample {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Sample {}
+
+impl Sample {
+    // Call again after changing path, a stale cache makes searches match the old one
+    pub fn refresh_path_cache(&mut self) {
+        self.path_cache = PathCache(Some(self.path.to_lowercase()));
+    }
+
+    fn path_lowercase(&self) -> Cow<'_, str> {
+        match &self.path_cache.0 {
+            Some(cached) => Cow::Borrowed(cached),
+            None => Cow::Owned(self.path.to_lowercase()),
+        }
+    }
+
+    pub fn increment_usage(&mut self) {
+        self.usage_count = Some(self.usage_count.unwrap_or(0).saturating_add(1));
+    }
+}
+
+// 16 hex digits of FNV-1a over the path with '/' separators. Unlike DefaultHasher
+// the result never changes between builds, so it can key data stored elsewhere.
+pub fn sample_id(sample: &Sample) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in sample.path.replace('\\', "/").bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{:016x}", hash)
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -38,6 +659,9 @@ pub struct PackInfo {
     pub name: String,
     pub img: Option<String>,
     num_samples: Option<u32>,
+    // Set on packs built by smart_pack from this search instead of scanned from disk
+    #[serde(default)]
+    pub source_query: Option<SearchParams>,
 }
 
 // Config object for **server side** pack recognition
@@ -47,6 +671,93 @@ pub struct LocalPackInfo {
     pub name: String,
 }
 
+// Scan settings for load_pack_with_options
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+#[serde(default)]
+pub struct LoadOptions {
+    // Symlinked folders are scanned like regular ones, so a folder linked into
+    // several packs shows up in each of them. Link cycles are skipped.
+    pub follow_links: bool,
+    // Reads WAV headers for sample durations, needs the audio feature
+    pub read_headers: bool,
+    // Skips 0-byte files, and with the audio feature WAVs with an empty data
+    // chunk or a peak amplitude (0.0 - 1.0) at or below silence_threshold
+    pub skip_silent: bool,
+    pub silence_threshold: f32,
+    // (low, high) band detected loop tempos are folded into, see normalize_tempo
+    pub normalize_tempo: Option<(u32, u32)>,
+    // Case-insensitive path substrings, blocked wins over allowed. An empty
+    // allow list allows everything.
+    pub blocked_folders: Vec<String>,
+    pub allowed_folders: Vec<String>,
+    // Folder names (case-insensitive, whole path components) that decide the
+    // type before keyword detection runs. The folder closest to the file wins.
+    pub loop_folders: Vec<String>,
+    pub oneshot_folders: Vec<String>,
+    // File name words (case-insensitive, singular or plural) of demo/preview renders
+    // that are skipped, so "demo" skips "song_demo.wav" but not "demolition.wav".
+    // None uses DEFAULT_PREVIEW_PATTERNS, an empty list keeps everything.
+    pub preview_patterns: Option<Vec<String>>,
+    // Keeps each sample's lowercased path in memory to speed up repeated searches
+    pub cache_paths: bool,
+    // Fills effective_duration_secs of WAVs with this threshold, needs the audio feature
+    pub trim_threshold_db: Option<f32>,
+    // Checked before normalize_tempo. Detection only reads tempos in 40..=300, so this
+    // is for narrower bands, e.g. min 60 keeps a take number like "45" out of tempos.
+    pub tempo_sanitize: Option<TempoSanitize>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OutOfRangeTempo {
+    OneShot,
+    // Stays a loop with tempo 0
+    Unknown,
+}
+
+// Loops with a tempo outside min..=max are handled by policy
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub struct TempoSanitize {
+    pub min: u32,
+    pub max: u32,
+    pub policy: OutOfRangeTempo,
+}
+
+impl TempoSanitize {
+    // Loop(0) means no tempo was detected and is left alone, it isn't out of range
+    pub fn apply(&self, sampletype: SampleType) -> SampleType {
+        match sampletype {
+            SampleType::Loop(tempo)
+                if tempo != 0 && (tempo < self.min as i32 || tempo > self.max as i32) =>
+            {
+                match self.policy {
+                    OutOfRangeTempo::OneShot => SampleType::OneShot,
+                    OutOfRangeTempo::Unknown => SampleType::Loop(0),
+                }
+            }
+            other => other,
+        }
+    }
+}
+
+pub const DEFAULT_PREVIEW_PATTERNS: [&str; 3] = ["preview", "demo", "watermark"];
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct ScanEstimate {
+    pub files: usize,
+    pub total_bytes: u64,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LoadReport {
+    pub skipped_silent: usize,
+    pub skipped_blocked: usize,
+    #[serde(default)]
+    pub skipped_preview: usize,
+    // The scan was stopped early, the pack only holds what was found until then
+    #[serde(default)]
+    pub cancelled: bool,
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Pack {
     pub samples: Vec<Sample>,
@@ -59,231 +770,5709 @@ pub struct SampleLibrary {
     pub name: String,
 }
 
-pub fn get_packs_metadata(lib: &SampleLibrary) -> Vec<PackInfo> {
-    let mut out: Vec<PackInfo> = vec![];
-    for pack in &lib.packs {
-        out.push(pack.meta.clone());
+impl SampleLibrary {
+    pub fn new(name: &str, packs: Vec<Pack>) -> SampleLibrary {
+        SampleLibrary {
+            packs,
+            name: name.to_string(),
+        }
     }
-    out
 }
 
-pub fn fuzzy_relevancy(path: &str, queries: &Vec<String>) -> i32 {
-    todo!();
+// A library with its sample count cached, for callers asking for it often.
+// Every change goes through library_mut, which clears the cached count.
+#[derive(Debug)]
+pub struct CountedLibrary {
+    lib: SampleLibrary,
+    total: OnceLock<usize>,
 }
 
-pub fn use_sample_relevance(
-    query: &SearchParams,
-    sample: &Sample,
-    text_queries: &Vec<&str>,
-) -> i32 {
-    // print!("New Sample: {}", sample.name);
-    if query.sample_type.is_some() {
-        if std::mem::discriminant(query.sample_type.as_ref().unwrap())
-            != std::mem::discriminant(&sample.sampletype)
-        {
-            // println!("Ignoring because of sampletype");
-            return 0;
+impl CountedLibrary {
+    pub fn new(lib: SampleLibrary) -> CountedLibrary {
+        CountedLibrary {
+            lib,
+            total: OnceLock::new(),
         }
     }
 
-    if query.sample_type.is_some() {
-        match query.sample_type.as_ref().unwrap() {
-            SampleType::Loop(tempo) => {
-                if query.min_tempo.is_some() && tempo.to_owned() as u32 > query.min_tempo.unwrap() {
-                    println!("TEMPO TO LOW");
-                    return 0;
-                }
-                if query.max_tempo.is_some() && (tempo.to_owned() as u32) < query.max_tempo.unwrap()
-                {
-                    println!("TEMPO TO HIGH");
-                    return 0;
-                }
-            }
-            SampleType::OneShot => (),
-        }
+    pub fn library(&self) -> &SampleLibrary {
+        &self.lib
     }
 
-    let mut relevancy = 0;
-    let mut is_filtered = false;
-    text_queries.iter().for_each(|token| {
-        if !token.is_empty() {
-            if token.starts_with('-') {
-                if sample
-                    .path
-                    .to_lowercase()
-                    .contains(&token.to_lowercase().replace("-", ""))
-                {
-                    is_filtered = true;
-                }
-            }
-            if sample.path.to_lowercase().contains(&token.to_lowercase()) {
-                relevancy += 1;
-            }
-        }
-    });
+    pub fn library_mut(&mut self) -> &mut SampleLibrary {
+        self.total.take();
+        &mut self.lib
+    }
+
+    // Same as sample_count, counted on first use after a change
+    pub fn total_samples(&self) -> usize {
+        *self.total.get_or_init(|| sample_count(&self.lib))
+    }
 
-    if is_filtered {
-        return 0;
+    pub fn into_inner(self) -> SampleLibrary {
+        self.lib
     }
-    return relevancy;
 }
 
-pub fn search_lib(lib: &SampleLibrary, query: &SearchParams) -> SearchResult {
-    let query_lowercase = query.query.to_lowercase();
-    let mut text_queries: Vec<&str> = query_lowercase.split(' ').collect();
-    text_queries.iter_mut().for_each(|s| *s = s.trim());
-    /* text_queries
-    .iter()
-    .for_each(|q| println!("QUERYUNIQUE: {}", q));*/
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ImportSummary {
+    pub added: usize,
+    pub skipped_paths: Vec<String>,
+}
 
-    let mut sorting_vec: Vec<(Sample, i32)> = vec![];
-    for pack in &lib.packs {
-        if query.pack_id.is_some() {
-            if !pack.meta.name.eq(query.pack_id.as_ref().unwrap()) {
-                continue;
-            }
+// Rejects packs whose name is taken, samples already present in another pack are skipped
+pub fn import_pack(lib: &mut SampleLibrary, mut pack: Pack) -> Result<ImportSummary, LibError> {
+    if lib.packs.iter().any(|p| p.meta.name == pack.meta.name) {
+        return Err(LibError::PackExists(pack.meta.name));
+    }
+    let existing: HashSet<&str> = all_samples(lib).map(|s| s.path.as_str()).collect();
+    let mut skipped_paths = vec![];
+    let mut samples = vec![];
+    for sample in pack.samples {
+        if existing.contains(sample.path.as_str()) {
+            skipped_paths.push(sample.path);
+        } else {
+            samples.push(sample);
         }
-
-        pack.samples.iter().for_each(|sample| {
-            let rev = use_sample_relevance(&query, &sample, &text_queries);
-            if rev > 0 {
-                sorting_vec.push(((*sample).clone(), rev));
-            }
-        });
     }
+    pack.samples = samples;
+    refresh_count(&mut pack);
+    let added = pack.samples.len();
+    lib.packs.push(pack);
+    Ok(ImportSummary {
+        added,
+        skipped_paths,
+    })
+}
 
-    sorting_vec.sort_by(|a, b| b.1.cmp(&a.1));
-
-    let max_results: usize = match query.max_results {
-        Some(input) => input as usize,
-        None => 10,
-    };
+// (pack, sample) position of the sample with exactly this path
+fn sample_position(lib: &SampleLibrary, path: &str) -> Option<(usize, usize)> {
+    lib.packs.iter().enumerate().find_map(|(pack_index, pack)| {
+        let sample_index = pack.samples.iter().position(|s| s.path == path)?;
+        Some((pack_index, sample_index))
+    })
+}
 
-    let ret_vec: Vec<Sample>;
-    if sorting_vec.len() >= max_results {
-        ret_vec = sorting_vec
-            .into_iter()
-            .take(max_results)
-            .map(|(e, _)| e)
-            .collect();
-    } else {
-        ret_vec = sorting_vec.into_iter().map(|element| element.0).collect();
+// Adds a single new file to the pack whose pack_root is the longest prefix of path.
+// Roots come from the samples, so a pack whose samples all sit in one subfolder
+// only takes files from it. Paths already in the library are returned unchanged,
+// files load_pack would skip give None.
+pub fn add_file<'a>(
+    lib: &'a mut SampleLibrary,
+    path: &str,
+    opts: &LoadOptions,
+) -> Result<Option<&'a Sample>, LibError> {
+    if let Some((pack_index, sample_index)) = sample_position(lib, path) {
+        return Ok(Some(&lib.packs[pack_index].samples[sample_index]));
     }
+    if is_skipped_file(path, opts) {
+        trace!("Skipping file: {}", path);
+        return Ok(None);
+    }
+    let pack_index = lib
+        .packs
+        .iter()
+        .enumerate()
+        .filter_map(|(index, pack)| Some((index, pack_root(pack)?)))
+        .filter(|(_, root)| path.starts_with(root.as_str()))
+        .max_by_key(|(_, root)| root.len())
+        .map(|(index, _)| index)
+        .ok_or_else(|| LibError::NoPackForPath(path.to_string()))?;
+    let pack = &mut lib.packs[pack_index];
+    pack.samples.push(sample_from_path(path, opts));
+    refresh_count(pack);
+    let sample_index = lib.packs[pack_index].samples.len() - 1;
+    Ok(Some(&lib.packs[pack_index].samples[sample_index]))
+}
+
+// Takes the sample with exactly this path out of its pack, None when no pack has it
+pub fn remove_file(lib: &mut SampleLibrary, path: &str) -> Option<Sample> {
+    let (pack_index, sample_index) = sample_position(lib, path)?;
+    let pack = &mut lib.packs[pack_index];
+    let sample = pack.samples.remove(sample_index);
+    refresh_count(pack);
+    Some(sample)
+}
 
-    SearchResult { samples: ret_vec }
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DiffEntry {
+    pub pack: String,
+    pub sample: Sample,
 }
 
-fn extract_tempo_braces(path: &str) -> Option<i32> {
-    let input = path;
-    let start_index = input.find('[')?;
-    let end_index = input[start_index + 1..].find(']')? + start_index + 1;
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SampleChange {
+    pub pack: String,
+    pub old: Sample,
+    pub new: Sample,
+}
 
-    input[start_index + 1..end_index].parse().ok()
+// Samples are matched by path, pack is always the owning pack on the new side
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct LibraryDiff {
+    pub added: Vec<DiffEntry>,
+    pub removed: Vec<DiffEntry>,
+    pub changed: Vec<SampleChange>,
 }
 
-fn detect_tempo_txt(path: &str) -> i32 {
-    let extracted = extract_tempo_braces(path);
-    match extracted {
-        Some(val) => {
-            return val;
+fn samples_by_path(lib: &SampleLibrary) -> HashMap<&str, (&str, &Sample)> {
+    lib.packs
+        .iter()
+        .flat_map(|pack| {
+            pack.samples
+                .iter()
+                .map(move |s| (s.path.as_str(), (pack.meta.name.as_str(), s)))
+        })
+        .collect()
+}
+
+// Reports added and removed paths, plus samples that changed type/tempo or moved pack
+pub fn diff_libraries(old: &SampleLibrary, new: &SampleLibrary) -> LibraryDiff {
+    let old_samples = samples_by_path(old);
+    let new_samples = samples_by_path(new);
+    let mut diff = LibraryDiff::default();
+
+    for pack in &new.packs {
+        for sample in &pack.samples {
+            match old_samples.get(sample.path.as_str()) {
+                None => diff.added.push(DiffEntry {
+                    pack: pack.meta.name.clone(),
+                    sample: sample.clone(),
+                }),
+                Some((old_pack, old_sample)) => {
+                    if old_sample.sampletype != sample.sampletype || *old_pack != pack.meta.name {
+                        diff.changed.push(SampleChange {
+                            pack: pack.meta.name.clone(),
+                            old: (*old_sample).clone(),
+                            new: sample.clone(),
+                        });
+                    }
+                }
+            }
         }
-        None => {
-            return 0;
+    }
+    for pack in &old.packs {
+        for sample in &pack.samples {
+            if !new_samples.contains_key(sample.path.as_str()) {
+                diff.removed.push(DiffEntry {
+                    pack: pack.meta.name.clone(),
+                    sample: sample.clone(),
+                });
+            }
         }
     }
+    diff
 }
 
-fn detect_type(path: &str) -> SampleType {
-    let loop_signals = [
-        "/loop",
-        "/construction",
-        "_loop",
-        "[",
-        "bpm",
-        "loop",
-        "loops",
-    ];
-    // todo: load keywords from json
-    let path_lower = path.to_lowercase();
-    for keyword in loop_signals {
-        if path_lower.contains(keyword) {
-            let tempo = detect_tempo_txt(&path_lower);
-            return SampleType::Loop(tempo);
-        }
-    }
-    SampleType::OneShot
+fn refresh_count(pack: &mut Pack) {
+    pack.meta.num_samples = Some(
+        pack.samples
+            .len()
+            .try_into()
+            .expect("Overflow: Too many samples in pack for u32"),
+    );
 }
 
-pub fn get_sample(path: &str) -> Sample {
-    let sample_type = detect_type(path);
-    let sample = Sample {
-        name: path.to_string(), // TODO: CUT OFF EVERYTHING BEFORE THE LAST "/"
-        path: path.to_string(),
-        sampletype: sample_type,
-    };
-    sample
+// Packs named in the diff that don't exist yet are created with empty metadata
+pub fn apply_diff(lib: &mut SampleLibrary, diff: &LibraryDiff) {
+    let outdated: HashSet<&str> = diff
+        .removed
+        .iter()
+        .map(|entry| entry.sample.path.as_str())
+        .chain(diff.changed.iter().map(|change| change.old.path.as_str()))
+        .collect();
+    for pack in &mut lib.packs {
+        pack.samples
+            .retain(|sample| !outdated.contains(sample.path.as_str()));
+    }
+
+    let incoming = diff
+        .added
+        .iter()
+        .map(|entry| (&entry.pack, &entry.sample))
+        .chain(
+            diff.changed
+                .iter()
+                .map(|change| (&change.pack, &change.new)),
+        );
+    for (pack_name, sample) in incoming {
+        let index = match lib.packs.iter().position(|p| &p.meta.name == pack_name) {
+            Some(index) => index,
+            None => {
+                lib.packs.push(Pack {
+                    samples: vec![],
+                    meta: PackInfo {
+                        description: String::new(),
+                        name: pack_name.clone(),
+                        img: None,
+                        num_samples: None,
+                        source_query: None,
+                    },
+                });
+                lib.packs.len() - 1
+            }
+        };
+        lib.packs[index].samples.push(sample.clone());
+    }
+
+    recompute_counts(lib);
 }
 
-pub fn load_pack(path: &str, name: &str, desc: &str) -> Pack {
-    let start_path = path.to_string();
-    let mut count_loop = 0;
-    let mut count_oneshot = 0;
-    let mut pack = Pack {
-        samples: vec![],
-        meta: PackInfo {
-            description: desc.to_string(),
-            name: name.to_string(),
-            img: None,
-            num_samples: None,
-        },
+pub fn recompute_counts(lib: &mut SampleLibrary) {
+    for pack in &mut lib.packs {
+        refresh_count(pack);
+    }
+}
+
+// True when num_samples is missing or disagrees with samples.len()
+pub fn counts_are_stale(pack: &Pack) -> bool {
+    pack.meta.num_samples != Some(pack.samples.len() as u32)
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum PackIssue {
+    MissingName,
+    MissingDescription,
+    MissingArtwork,
+    EmptyPack,
+    StaleSampleCount,
+}
+
+// Checks a pack is ready to publish, blank strings count as missing
+pub fn audit_pack(pack: &Pack) -> Vec<PackIssue> {
+    let mut issues = vec![];
+    if pack.meta.name.trim().is_empty() {
+        issues.push(PackIssue::MissingName);
+    }
+    if pack.meta.description.trim().is_empty() {
+        issues.push(PackIssue::MissingDescription);
+    }
+    if pack
+        .meta
+        .img
+        .as_ref()
+        .is_none_or(|img| img.trim().is_empty())
+    {
+        issues.push(PackIssue::MissingArtwork);
+    }
+    if pack.samples.is_empty() {
+        issues.push(PackIssue::EmptyPack);
+    }
+    if counts_are_stale(pack) {
+        issues.push(PackIssue::StaleSampleCount);
+    }
+    issues
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum IntegrityIssue {
+    // pack_id searches only ever see the first pack with a name
+    DuplicatePackName(String),
+    EmptySamplePath { pack: String },
+    StaleSampleCount { pack: String },
+    ZeroTempoLoop { path: String },
+}
+
+// Problems a library can load with but that make searches behave oddly
+pub fn validate_library_integrity(lib: &SampleLibrary) -> Vec<IntegrityIssue> {
+    let mut issues = vec![];
+    let mut names: HashSet<&str> = HashSet::new();
+    for pack in &lib.packs {
+        let name = pack.meta.name.as_str();
+        if !names.insert(name) {
+            issues.push(IntegrityIssue::DuplicatePackName(name.to_string()));
+        }
+        if counts_are_stale(pack) {
+            issues.push(IntegrityIssue::StaleSampleCount {
+                pack: name.to_string(),
+            });
+        }
+        for sample in &pack.samples {
+            if sample.path.trim().is_empty() {
+                issues.push(IntegrityIssue::EmptySamplePath {
+                    pack: name.to_string(),
+                });
+            } else if let SampleType::Loop(_) = sample.sampletype {
+                if sample.sampletype.tempo().is_none() {
+                    issues.push(IntegrityIssue::ZeroTempoLoop {
+                        path: sample.path.clone(),
+                    });
+                }
+            }
+        }
+    }
+    issues
+}
+
+// Longest directory prefix shared by all sample paths, with its trailing separator.
+// None for an empty library or when paths don't share a root (e.g. C:\ and D:\).
+pub fn common_root(lib: &SampleLibrary) -> Option<String> {
+    common_dir(all_samples(lib))
+}
+
+// common_root of a single pack, the folder of its only sample if it has one
+pub fn pack_root(pack: &Pack) -> Option<String> {
+    common_dir(pack.samples.iter())
+}
+
+fn common_dir<'a>(mut samples: impl Iterator<Item = &'a Sample>) -> Option<String> {
+    let first = &samples.next()?.path;
+    let first_dirs: Vec<&str> = first.split(['/', '\\']).collect();
+    // The last component is the file name
+    let mut common = first_dirs.len() - 1;
+    for sample in samples {
+        let dirs: Vec<&str> = sample.path.split(['/', '\\']).collect();
+        common = first_dirs[..common]
+            .iter()
+            .zip(&dirs[..dirs.len() - 1])
+            .take_while(|(a, b)| a == b)
+            .count();
+    }
+    if common == 0 {
+        return None;
+    }
+    let root_len: usize = first_dirs[..common].iter().map(|c| c.len() + 1).sum();
+    Some(first[..root_len].to_string())
+}
+
+// Every sample of every pack, in pack order
+pub fn all_samples(lib: &SampleLibrary) -> impl Iterator<Item = &Sample> {
+    lib.packs.iter().flat_map(|pack| pack.samples.iter())
+}
+
+pub fn sample_count(lib: &SampleLibrary) -> usize {
+    lib.packs.iter().map(|pack| pack.samples.len()).sum()
+}
+
+pub fn get_packs_metadata(lib: &SampleLibrary) -> Vec<PackInfo> {
+    let mut out: Vec<PackInfo> = vec![];
+    for pack in &lib.packs {
+        out.push(pack.meta.clone());
+    }
+    out
+}
+
+// Packs by name ignoring case, ties keep their load order
+pub fn sorted_packs(lib: &SampleLibrary) -> Vec<&Pack> {
+    let mut packs: Vec<&Pack> = lib.packs.iter().collect();
+    packs.sort_by_cached_key(|pack| pack.meta.name.to_lowercase());
+    packs
+}
+
+pub fn sort_packs(lib: &mut SampleLibrary) {
+    lib.packs
+        .sort_by_cached_key(|pack| pack.meta.name.to_lowercase());
+}
+
+fn find_with_pack<'a>(lib: &'a SampleLibrary, path: &str) -> Option<(&'a Pack, &'a Sample)> {
+    lib.packs.iter().find_map(|pack| {
+        pack.samples
+            .iter()
+            .find(|s| s.path == path)
+            .map(|sample| (pack, sample))
+    })
+}
+
+// Linear scan, build a HashMap from path_index for repeated lookups
+pub fn find_by_path<'a>(lib: &'a SampleLibrary, path: &str) -> Option<&'a Sample> {
+    find_with_pack(lib, path).map(|(_, sample)| sample)
+}
+
+// '/' separators, no empty or "." components and ".." folded into its parent
+fn normalize_path(path: &str) -> String {
+    let mut components: Vec<&str> = vec![];
+    for component in path.split(['/', '\\']) {
+        match component {
+            "" | "." => {}
+            ".." if components.last().is_some_and(|last| *last != "..") => {
+                components.pop();
+            }
+            _ => components.push(component),
+        }
+    }
+    let joined = components.join("/");
+    if path.starts_with(['/', '\\']) {
+        "/".to_string() + &joined
+    } else {
+        joined
+    }
+}
+
+// Pack name and sample for path, compared after normalize_path.
+// Relative paths are resolved against common_root.
+pub fn locate_sample<'a>(lib: &'a SampleLibrary, path: &str) -> Option<(String, &'a Sample)> {
+    let mut wanted = normalize_path(path);
+    let is_absolute = path.starts_with(['/', '\\']) || path.get(1..2) == Some(":");
+    if !is_absolute {
+        if let Some(root) = common_root(lib) {
+            wanted = normalize_path(&(root + path));
+        }
+    }
+    lib.packs.iter().find_map(|pack| {
+        pack.samples
+            .iter()
+            .find(|sample| normalize_path(&sample.path) == wanted)
+            .map(|sample| (pack.meta.name.clone(), sample))
+    })
+}
+
+// Swaps the old_root folder prefix of sample paths for new_root and returns how many
+// were rebased. Only whole folders match, "/old" leaves "/older/kick.wav" alone.
+pub fn rebase_paths(lib: &mut SampleLibrary, old_root: &str, new_root: &str) -> usize {
+    let old_root = old_root.trim_end_matches(['/', '\\']);
+    let new_root = new_root.trim_end_matches(['/', '\\']);
+    let mut rebased = 0;
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            let Some(rest) = sample.path.strip_prefix(old_root) else {
+                continue;
+            };
+            if !rest.starts_with(['/', '\\']) {
+                continue;
+            }
+            sample.path = format!("{}{}", new_root, rest);
+            if sample.path_cache.0.is_some() {
+                sample.refresh_path_cache();
+            }
+            rebased += 1;
+        }
+    }
+    rebased
+}
+
+// Maps each sample path to its (pack, sample) position in lib.packs
+pub fn path_index(lib: &SampleLibrary) -> HashMap<String, (usize, usize)> {
+    let mut index = HashMap::new();
+    for (pack_index, pack) in lib.packs.iter().enumerate() {
+        for (sample_index, sample) in pack.samples.iter().enumerate() {
+            index.insert(sample.path.clone(), (pack_index, sample_index));
+        }
+    }
+    index
+}
+
+// Fraction of loops per pack, empty packs report 0.0
+pub fn pack_type_ratios(lib: &SampleLibrary) -> Vec<(String, f32)> {
+    lib.packs
+        .iter()
+        .map(|pack| {
+            let loops = pack
+                .samples
+                .iter()
+                .filter(|s| matches!(s.sampletype, SampleType::Loop(_)))
+                .count();
+            let ratio = if pack.samples.is_empty() {
+                0.0
+            } else {
+                loops as f32 / pack.samples.len() as f32
+            };
+            (pack.meta.name.clone(), ratio)
+        })
+        .collect()
+}
+
+fn name_token_set(pack: &Pack) -> HashSet<String> {
+    pack.samples
+        .iter()
+        .flat_map(|sample| {
+            let stem = std::path::Path::new(&sample.name)
+                .file_stem()
+                .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+            path_tokens(&stem)
+        })
+        .collect()
+}
+
+// Jaccard similarity (shared / combined) of the words in both packs' sample names,
+// extensions left out. Two packs without any words score 0.0.
+pub fn pack_similarity(a: &Pack, b: &Pack) -> f32 {
+    let a_tokens = name_token_set(a);
+    let b_tokens = name_token_set(b);
+    let union = a_tokens.union(&b_tokens).count();
+    if union == 0 {
+        return 0.0;
+    }
+    a_tokens.intersection(&b_tokens).count() as f32 / union as f32
+}
+
+// Lowest and highest loop tempo, a detected tempo_range counts with both ends.
+// None when the pack has no loop with a known tempo.
+pub fn pack_tempo_range(pack: &Pack) -> Option<(u32, u32)> {
+    pack.samples
+        .iter()
+        .filter_map(|sample| {
+            let tempo = sample.sampletype.tempo()?;
+            Some(sample.tempo_range.unwrap_or((tempo, tempo)))
+        })
+        .reduce(|(low, high), (sample_low, sample_high)| {
+            (low.min(sample_low), high.max(sample_high))
+        })
+}
+
+// Loop tempo bands pack_coverage checks, from low up to but excluding high
+pub const DEFAULT_TEMPO_BANDS: [(u32, u32); 8] = [
+    (60, 80),
+    (80, 90),
+    (90, 100),
+    (100, 110),
+    (110, 120),
+    (120, 130),
+    (130, 140),
+    (140, 180),
+];
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CoverageReport {
+    pub present_categories: Vec<DrumCategory>,
+    pub missing_categories: Vec<DrumCategory>,
+    pub present_bands: Vec<(u32, u32)>,
+    pub missing_bands: Vec<(u32, u32)>,
+}
+
+pub fn pack_coverage(pack: &Pack) -> CoverageReport {
+    pack_coverage_with(pack, &default_drum_keywords(), &DEFAULT_TEMPO_BANDS)
+}
+
+// Categories and bands come back in the order given. A loop with a detected
+// tempo_range covers every band the range overlaps.
+pub fn pack_coverage_with(
+    pack: &Pack,
+    keywords: &[(DrumCategory, Vec<String>)],
+    bands: &[(u32, u32)],
+) -> CoverageReport {
+    let categories: HashSet<DrumCategory> = pack
+        .samples
+        .iter()
+        .filter_map(|sample| drum_category_with(sample, keywords))
+        .collect();
+    let tempo_ranges: Vec<(u32, u32)> = pack
+        .samples
+        .iter()
+        .filter_map(|sample| {
+            let tempo = sample.sampletype.tempo()?;
+            Some(sample.tempo_range.unwrap_or((tempo, tempo)))
+        })
+        .collect();
+    let mut report = CoverageReport {
+        present_categories: vec![],
+        missing_categories: vec![],
+        present_bands: vec![],
+        missing_bands: vec![],
     };
+    for (category, _) in keywords {
+        match categories.contains(category) {
+            true => report.present_categories.push(*category),
+            false => report.missing_categories.push(*category),
+        }
+    }
+    for &(band_low, band_high) in bands {
+        let covered = tempo_ranges
+            .iter()
+            .any(|&(low, high)| low < band_high && high >= band_low);
+        match covered {
+            true => report.present_bands.push((band_low, band_high)),
+            false => report.missing_bands.push((band_low, band_high)),
+        }
+    }
+    report
+}
 
-    for entry in WalkDir::new(start_path).into_iter().filter_map(|e| e.ok()) {
-        let entry_path = entry.path().display().to_string();
-        let entry_name = entry
-            .file_name()
-            .to_str()
-            .expect("Couldnt get entry name")
-            .to_string();
+// count samples chosen by date_seed, the same on every device and release for a day.
+// A sample used n times is picked 1 / (n + 1) as often as an unused one.
+pub fn daily_picks(lib: &SampleLibrary, date_seed: u64, count: usize) -> Vec<&Sample> {
+    let mut rng = ChaCha8Rng::seed_from_u64(date_seed);
+    // Weighted sampling without replacement: keep the largest u^(1 / weight)
+    let mut keyed: Vec<(f64, &Sample)> = all_samples(lib)
+        .map(|sample| {
+            let weight = 1.0 / (1.0 + sample.usage_count.unwrap_or(0) as f64);
+            (rng.gen::<f64>().powf(1.0 / weight), sample)
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    keyed
+        .into_iter()
+        .take(count)
+        .map(|(_, sample)| sample)
+        .collect()
+}
 
-        if entry_name.contains(".wav") || entry_name.contains(".mp3") {
-            let stype = detect_type(&entry_path.to_lowercase());
-            match stype {
-                SampleType::OneShot => {
-                    count_oneshot += 1;
+// Loops closest to reference first, ties by name. Loops without a tempo (0) are left out.
+pub fn by_tempo_proximity(lib: &SampleLibrary, reference: u32) -> Vec<&Sample> {
+    let mut loops: Vec<(u32, &Sample)> = all_samples(lib)
+        .filter_map(|sample| Some((sample.sampletype.tempo()?, sample)))
+        .collect();
+    loops.sort_by(|(a_tempo, a), (b_tempo, b)| {
+        a_tempo
+            .abs_diff(reference)
+            .cmp(&b_tempo.abs_diff(reference))
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    loops.into_iter().map(|(_, sample)| sample).collect()
+}
+
+// Words of a file name without the extension, numbers (tempos, take counts) and "bpm"
+fn groove_words(sample: &Sample) -> HashSet<String> {
+    let file_name = file_name_of(&sample.path);
+    let stem = std::path::Path::new(&file_name)
+        .file_stem()
+        .map_or(String::new(), |stem| stem.to_string_lossy().to_string());
+    path_tokens(&stem)
+        .into_iter()
+        .filter(|token| token != "bpm" && !token.contains(|c: char| c.is_ascii_digit()))
+        .collect()
+}
+
+// Loops at exactly half the tempo of another loop whose file name shares at least
+// half of its words, (slower, faster). Names are compared without their numbers.
+// Files without a loop keyword, like "groove_120.wav", use the tempo in their name.
+pub fn halftime_pairs(lib: &SampleLibrary) -> Vec<(Sample, Sample)> {
+    let mut by_tempo: BTreeMap<u32, Vec<(&Sample, HashSet<String>)>> = BTreeMap::new();
+    for sample in all_samples(lib) {
+        let tempo = sample
+            .sampletype
+            .tempo()
+            .or_else(|| resolve_tempo(&file_name_of(&sample.path)).tempo);
+        if let Some(tempo) = tempo {
+            by_tempo
+                .entry(tempo)
+                .or_default()
+                .push((sample, groove_words(sample)));
+        }
+    }
+    let mut pairs = vec![];
+    for (tempo, slower) in &by_tempo {
+        let Some(faster) = by_tempo.get(&(tempo * 2)) else {
+            continue;
+        };
+        for (slow, slow_words) in slower {
+            for (fast, fast_words) in faster {
+                let shared = slow_words.intersection(fast_words).count();
+                let combined = slow_words.union(fast_words).count();
+                if shared > 0 && shared * 2 >= combined {
+                    pairs.push(((*slow).clone(), (*fast).clone()));
                 }
-                SampleType::Loop(_) => {
-                    count_loop += 1;
+            }
+        }
+    }
+    pairs
+}
+
+const TOKEN_STOPWORDS: [&str; 9] = [
+    "the", "and", "for", "with", "wav", "mp3", "aif", "aiff", "flac",
+];
+
+// Most common words in sample paths below common_root, most frequent first and ties
+// alphabetical. Words under 3 characters, numbers and TOKEN_STOPWORDS are skipped.
+pub fn token_frequencies(lib: &SampleLibrary, top_n: usize) -> Vec<(String, usize)> {
+    let root_len = common_root(lib).map_or(0, |root| root.len());
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for sample in all_samples(lib) {
+        let relative = sample.path.get(root_len..).unwrap_or(&sample.path);
+        for token in path_tokens(relative) {
+            if token.chars().count() < 3
+                || token.chars().all(|c| c.is_ascii_digit())
+                || TOKEN_STOPWORDS.contains(&token.as_str())
+            {
+                continue;
+            }
+            *counts.entry(token).or_insert(0) += 1;
+        }
+    }
+    let mut frequencies: Vec<(String, usize)> = counts.into_iter().collect();
+    frequencies.sort_by(|(a, a_count), (b, b_count)| b_count.cmp(a_count).then_with(|| a.cmp(b)));
+    frequencies.truncate(top_n);
+    frequencies
+}
+
+// Lowercased words of a path, split on anything that isn't alphanumeric
+fn path_tokens(path: &str) -> Vec<String> {
+    path.to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|token| !token.is_empty())
+        .map(|token| token.to_string())
+        .collect()
+}
+
+fn complete_from<'a>(
+    paths: impl Iterator<Item = &'a str>,
+    prefix: &str,
+    limit: usize,
+) -> Vec<String> {
+    let prefix = prefix.to_lowercase();
+    let mut frequencies: HashMap<String, usize> = HashMap::new();
+    for path in paths {
+        for token in path_tokens(path) {
+            if token.starts_with(&prefix) {
+                *frequencies.entry(token).or_insert(0) += 1;
+            }
+        }
+    }
+    let mut suggestions: Vec<(String, usize)> = frequencies.into_iter().collect();
+    suggestions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    suggestions
+        .into_iter()
+        .take(limit)
+        .map(|(token, _)| token)
+        .collect()
+}
+
+// Most frequent path words starting with prefix, case-insensitive
+pub fn autocomplete(lib: &SampleLibrary, prefix: &str, limit: usize) -> Vec<String> {
+    complete_from(all_samples(lib).map(|s| s.path.as_str()), prefix, limit)
+}
+
+// Like autocomplete but from the sample names of one pack, folder words aren't suggested
+pub fn autocomplete_in_pack(pack: &Pack, prefix: &str, limit: usize) -> Vec<String> {
+    complete_from(pack.samples.iter().map(|s| s.name.as_str()), prefix, limit)
+}
+
+fn extension_of(path: &str) -> Option<String> {
+    std::path::Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+// Container format from the first 12 bytes of a file
+fn sniff_format(header: &[u8]) -> Option<&'static str> {
+    match header {
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..]
+        | [b'R', b'F', b'6', b'4', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some("wav"),
+        [b'I', b'D', b'3', ..] => Some("mp3"),
+        [0xFF, second, ..] if second & 0xE0 == 0xE0 => Some("mp3"),
+        [b'f', b'L', b'a', b'C', ..] => Some("flac"),
+        [b'O', b'g', b'g', b'S', ..] => Some("ogg"),
+        [b'F', b'O', b'R', b'M', _, _, _, _, b'A', b'I', b'F', ..] => Some("aiff"),
+        _ => None,
+    }
+}
+
+// Samples whose contents don't match their extension, with the format they really are
+// ("unknown" when unrecognized). Files that can't be read are left out.
+pub fn extension_mismatches(lib: &SampleLibrary) -> Vec<(Sample, String)> {
+    let mut mismatches = vec![];
+    for sample in all_samples(lib) {
+        let expected = match extension_of(&sample.path).as_deref() {
+            Some("aif") => "aiff".to_string(),
+            Some(extension) => extension.to_string(),
+            None => continue,
+        };
+        let mut header = vec![];
+        let read = File::open(&sample.path).and_then(|file| file.take(12).read_to_end(&mut header));
+        if read.is_err() {
+            continue;
+        }
+        let actual = sniff_format(&header).unwrap_or("unknown");
+        if actual != expected {
+            mismatches.push((sample.clone(), actual.to_string()));
+        }
+    }
+    mismatches
+}
+
+// Most common lowercase extension, ties go to the alphabetically first one
+pub fn pack_dominant_format(pack: &Pack) -> Option<String> {
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for sample in &pack.samples {
+        if let Some(extension) = extension_of(&sample.path) {
+            *counts.entry(extension).or_insert(0) += 1;
+        }
+    }
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(extension, _)| extension)
+}
+
+// Samples modified after `since`, newest first. Samples without a time are skipped.
+pub fn added_since(lib: &SampleLibrary, since: SystemTime) -> Vec<&Sample> {
+    let mut samples: Vec<&Sample> = all_samples(lib)
+        .filter(|sample| sample.modified.is_some_and(|modified| modified > since))
+        .collect();
+    samples.sort_by_key(|sample| Reverse(sample.modified));
+    samples
+}
+
+// The folder right below a "construction" folder is a kit, e.g.
+// ".../Construction Kits/Kit A/bass.wav" groups under ".../Construction Kits/Kit A".
+// Samples outside a construction folder are left out.
+pub fn group_construction_kits(pack: &Pack) -> Vec<(String, Vec<Sample>)> {
+    let mut kits: Vec<(String, Vec<Sample>)> = vec![];
+    for sample in &pack.samples {
+        let components: Vec<&str> = sample.path.split(['/', '\\']).collect();
+        let Some(position) = components
+            .iter()
+            .position(|c| c.to_lowercase().contains("construction"))
+        else {
+            continue;
+        };
+        // The kit folder needs at least the file itself below it
+        if position + 2 >= components.len() {
+            continue;
+        }
+        let kit_len: usize = components[..position + 2].iter().map(|c| c.len() + 1).sum();
+        let kit_folder = sample.path[..kit_len - 1].to_string();
+        match kits.iter_mut().find(|(folder, _)| *folder == kit_folder) {
+            Some((_, samples)) => samples.push(sample.clone()),
+            None => kits.push((kit_folder, vec![sample.clone()])),
+        }
+    }
+    kits
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum KitTempo {
+    Single(u32),
+    // Distinct loop tempos, ascending
+    Multi(Vec<u32>),
+    Unknown,
+}
+
+// Loops may differ this much (in BPM) from the most common tempo and still count as one tempo
+const KIT_TEMPO_TOLERANCE: u32 = 2;
+
+// Tempo of a kit from group_construction_kits. Single is the most common loop tempo
+// (the lower one on ties), one-shots and loops without a tempo are ignored.
+pub fn kit_tempo(samples: &[Sample]) -> KitTempo {
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for tempo in samples
+        .iter()
+        .filter_map(|sample| sample.sampletype.tempo())
+    {
+        *counts.entry(tempo).or_insert(0) += 1;
+    }
+    let Some((&common, _)) = counts
+        .iter()
+        .max_by(|(a, a_count), (b, b_count)| a_count.cmp(b_count).then_with(|| b.cmp(a)))
+    else {
+        return KitTempo::Unknown;
+    };
+    if counts
+        .keys()
+        .all(|tempo| tempo.abs_diff(common) <= KIT_TEMPO_TOLERANCE)
+    {
+        return KitTempo::Single(common);
+    }
+    let mut tempos: Vec<u32> = counts.into_keys().collect();
+    tempos.sort();
+    KitTempo::Multi(tempos)
+}
+
+pub const DEFAULT_VERSION_SUFFIXES: [&str; 6] = ["final", "version", "take", "ver", "alt", "v"];
+
+const VERSION_SEPARATORS: [char; 4] = ['_', '-', ' ', '.'];
+
+// Lowercased file stem with trailing version parts removed while something is left:
+// a number ("kick_01"), a suffix ("kick_final") or a suffix with a number ("kick_v2").
+// Each part has to follow a separator, so "tr808" keeps its digits.
+fn version_stem(name: &str, suffixes: &[&str]) -> String {
+    let file_stem = std::path::Path::new(name)
+        .file_stem()
+        .map_or(name.to_string(), |stem| stem.to_string_lossy().to_string());
+    let mut stem = file_stem.to_lowercase();
+    loop {
+        let trimmed = stem.trim_end_matches(VERSION_SEPARATORS);
+        let without_number = trimmed.trim_end_matches(|c: char| c.is_ascii_digit());
+        let mut next = None;
+        if without_number.len() < trimmed.len() && without_number.ends_with(VERSION_SEPARATORS) {
+            next = Some(without_number);
+        } else {
+            for suffix in suffixes {
+                if let Some(rest) = without_number.strip_suffix(&suffix.to_lowercase()) {
+                    if rest.ends_with(VERSION_SEPARATORS) {
+                        next = Some(rest);
+                        break;
+                    }
                 }
             }
-            pack.samples.push(Sample {
-                path: entry_path,
-                name: entry_name.clone(),
-                sampletype: stype,
-            });
-            println!("Sample found: {}", &entry_name);
+        }
+        match next {
+            Some(rest) if !rest.trim_end_matches(VERSION_SEPARATORS).is_empty() => {
+                stem = rest.to_string()
+            }
+            _ => return trimmed.to_string(),
         }
     }
-    println!("Loops: {count_loop}, OneShots: {count_oneshot}");
-    pack.meta.num_samples = Some(
-        pack.samples
-            .len()
-            .try_into()
-            .expect("Overflow: Too many samples in pack for u32"),
-    );
-    pack
 }
 
-pub fn save_lib_json(lib: &SampleLibrary, folder_path: &str) {
-    let json_lib = serde_json::to_string_pretty(&lib).expect("Couldnt create json data!");
-    let mut file =
-        File::create(folder_path.to_string() + &lib.name + ".json").expect("Couldnt open file!!");
-    file.write(json_lib.as_bytes())
-        .expect("Couldnt write file!!");
+// Samples whose names only differ by version suffixes, see version_stem.
+// Every sample is in exactly one group, in the order groups are first seen.
+pub fn group_versions(pack: &Pack) -> Vec<Vec<Sample>> {
+    group_versions_with(pack, &DEFAULT_VERSION_SUFFIXES)
 }
 
-pub fn load_lib_json(path: &str) -> SampleLibrary {
-    let content = std::fs::read_to_string(path).expect("Couldn't read json file");
-    let lib: SampleLibrary = serde_json::from_str(&content).expect("Couldn't parse json");
-    lib
+pub fn group_versions_with(pack: &Pack, suffixes: &[&str]) -> Vec<Vec<Sample>> {
+    let mut groups: Vec<(String, Vec<Sample>)> = vec![];
+    for sample in &pack.samples {
+        let stem = version_stem(&sample.name, suffixes);
+        match groups
+            .iter_mut()
+            .find(|(group_stem, _)| *group_stem == stem)
+        {
+            Some((_, samples)) => samples.push(sample.clone()),
+            None => groups.push((stem, vec![sample.clone()])),
+        }
+    }
+    groups.into_iter().map(|(_, samples)| samples).collect()
+}
+
+// A token counts as a fuzzy hit when its skim score reaches this much per
+// character, about half of what a run of consecutive matching characters
+// earns, so widely scattered subsequence matches are ignored
+const FUZZY_MIN_SCORE_PER_CHAR: i64 = 8;
+
+fn fuzzy_hit(matcher: &SkimMatcherV2, path_lowercase: &str, token: &str) -> bool {
+    matcher
+        .fuzzy_match(path_lowercase, token)
+        .is_some_and(|score| score >= FUZZY_MIN_SCORE_PER_CHAR * token.chars().count() as i64)
+}
+
+// Number of queries that fuzzily match the path
+pub fn fuzzy_relevancy(path: &str, queries: &Vec<String>) -> i32 {
+    let matcher = SkimMatcherV2::default();
+    let path_lowercase = path.to_lowercase();
+    queries
+        .iter()
+        .filter(|q| !q.is_empty() && fuzzy_hit(&matcher, &path_lowercase, &q.to_lowercase()))
+        .count() as i32
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct RelevanceExplanation {
+    pub matched_tokens: Vec<String>,
+    // Tokens that only matched fuzzily, see SearchParams::fuzzy
+    pub fuzzy_tokens: Vec<String>,
+    pub negated_tokens: Vec<String>,
+    pub excluded_by_type: bool,
+    pub excluded_by_tempo: bool,
+    pub excluded_by_bars: bool,
+    pub excluded_by_pack: bool,
+    #[serde(default)]
+    pub excluded_by_key: bool,
+    pub tempo_distance: Option<u32>,
+    pub score: f32,
+}
+
+impl RelevanceExplanation {
+    // Matched, but not by a single exact substring token
+    pub fn is_fuzzy_only(&self) -> bool {
+        self.matched_tokens.is_empty() && !self.fuzzy_tokens.is_empty()
+    }
+}
+
+// 1 + decay + decay^2 + ... for each occurrence of token in path
+fn occurrence_score(path_lowercase: &str, token: &str, decay: Option<f32>) -> f32 {
+    let decay = match decay {
+        Some(decay) => decay,
+        None => return 1.0,
+    };
+    let mut score = 0.0;
+    let mut step = 1.0;
+    for _ in path_lowercase.matches(token) {
+        score += step;
+        step *= decay;
+    }
+    score
+}
+
+const QUERY_DELIMITERS: [char; 4] = [' ', ',', ';', '\t'];
+
+// Query tokens split on any of QUERY_DELIMITERS, a leading '-' negation stays on its token
+pub fn split_query(query_lowercase: &str) -> Vec<&str> {
+    query_lowercase
+        .split(QUERY_DELIMITERS)
+        .map(|s| s.trim())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+// Boolean query tree, terms are matched as lowercase path substrings
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueryExpr {
+    Term(String),
+    Not(Box<QueryExpr>),
+    And(Box<QueryExpr>, Box<QueryExpr>),
+    Or(Box<QueryExpr>, Box<QueryExpr>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryParseError(pub String);
+
+impl fmt::Display for QueryParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid query: {}", self.0)
+    }
+}
+
+impl std::error::Error for QueryParseError {}
+
+impl QueryExpr {
+    pub fn matches(&self, path_lowercase: &str) -> bool {
+        match self {
+            QueryExpr::Term(term) => path_lowercase.contains(term.as_str()),
+            QueryExpr::Not(inner) => !inner.matches(path_lowercase),
+            QueryExpr::And(a, b) => a.matches(path_lowercase) && b.matches(path_lowercase),
+            QueryExpr::Or(a, b) => a.matches(path_lowercase) || b.matches(path_lowercase),
+        }
+    }
+
+    // Terms that aren't negated, these are what a match scores on. Each term is
+    // listed once, in the order it first appears.
+    pub fn positive_terms(&self) -> Vec<&str> {
+        let mut terms = vec![];
+        self.collect_positive_terms(&mut terms);
+        terms
+    }
+
+    fn collect_positive_terms<'a>(&'a self, terms: &mut Vec<&'a str>) {
+        match self {
+            QueryExpr::Term(term) => {
+                if !terms.contains(&term.as_str()) {
+                    terms.push(term.as_str());
+                }
+            }
+            QueryExpr::Not(_) => {}
+            QueryExpr::And(a, b) | QueryExpr::Or(a, b) => {
+                a.collect_positive_terms(terms);
+                b.collect_positive_terms(terms);
+            }
+        }
+    }
+}
+
+fn lex_query(query: &str) -> Vec<String> {
+    let mut tokens = vec![];
+    let mut word = String::new();
+    for c in query.chars() {
+        let delimiter = c.is_whitespace() || QUERY_DELIMITERS.contains(&c);
+        if delimiter || c == '(' || c == ')' {
+            if !word.is_empty() {
+                tokens.push(std::mem::take(&mut word));
+            }
+            if !delimiter {
+                tokens.push(c.to_string());
+            }
+        } else {
+            word.push(c);
+        }
+    }
+    if !word.is_empty() {
+        tokens.push(word);
+    }
+    tokens
+}
+
+struct QueryParser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl QueryParser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn parse_or(&mut self) -> Result<QueryExpr, QueryParseError> {
+        let mut expr = self.parse_and()?;
+        while self.peek() == Some("OR") {
+            self.position += 1;
+            expr = QueryExpr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        Ok(expr)
+    }
+
+    // Adjacent terms without an operator are ANDed
+    fn parse_and(&mut self) -> Result<QueryExpr, QueryParseError> {
+        let mut expr = self.parse_unary()?;
+        loop {
+            match self.peek() {
+                Some("AND") => self.position += 1,
+                Some("OR") | Some(")") | None => return Ok(expr),
+                Some(_) => (),
+            }
+            expr = QueryExpr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+    }
+
+    fn parse_unary(&mut self) -> Result<QueryExpr, QueryParseError> {
+        let token = match self.peek() {
+            Some(token) => token.to_string(),
+            None => return Err(QueryParseError("unexpected end of query".to_string())),
+        };
+        self.position += 1;
+        match token.as_str() {
+            "NOT" => Ok(QueryExpr::Not(Box::new(self.parse_unary()?))),
+            "(" => {
+                let expr = self.parse_or()?;
+                if self.peek() != Some(")") {
+                    return Err(QueryParseError("missing closing parenthesis".to_string()));
+                }
+                self.position += 1;
+                Ok(expr)
+            }
+            ")" | "AND" | "OR" => Err(QueryParseError(format!("unexpected \"{}\"", token))),
+            _ => match token.strip_prefix('-') {
+                Some(negated) if !negated.is_empty() => Ok(QueryExpr::Not(Box::new(
+                    QueryExpr::Term(negated.to_lowercase()),
+                ))),
+                _ => Ok(QueryExpr::Term(token.to_lowercase())),
+            },
+        }
+    }
+}
+
+// Operators are the uppercase words AND, OR and NOT, "-term" is NOT term
+pub fn parse_query_expr(query: &str) -> Result<QueryExpr, QueryParseError> {
+    let mut parser = QueryParser {
+        tokens: lex_query(query),
+        position: 0,
+    };
+    let expr = parser.parse_or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(QueryParseError(format!("unexpected \"{}\"", token))),
+    }
+}
+
+// Number of samples whose path contains each word, for tf_idf scoring
+#[derive(Debug, Serialize, Deserialize, Clone, Default)]
+pub struct SearchIndex {
+    pub doc_count: usize,
+    pub doc_freq: HashMap<String, usize>,
+}
+
+impl SearchIndex {
+    pub fn build(lib: &SampleLibrary) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        for sample in lib.packs.iter().flat_map(|pack| &pack.samples) {
+            index.doc_count += 1;
+            let words: HashSet<String> = path_tokens(&sample.path).into_iter().collect();
+            for word in words {
+                *index.doc_freq.entry(word).or_insert(0) += 1;
+            }
+        }
+        index
+    }
+
+    // Tokens that are only part of a word take the most frequent word containing them
+    pub fn document_frequency(&self, token: &str) -> usize {
+        match self.doc_freq.get(token) {
+            Some(count) => *count,
+            None => self
+                .doc_freq
+                .iter()
+                .filter(|(word, _)| word.contains(token))
+                .map(|(_, count)| *count)
+                .max()
+                .unwrap_or(0),
+        }
+    }
+
+    // Smoothed so a token in every sample still weighs 1
+    pub fn idf(&self, token: &str) -> f32 {
+        let docs = self.doc_count as f32 + 1.0;
+        let freq = self.document_frequency(token) as f32 + 1.0;
+        (docs / freq).ln() + 1.0
+    }
+}
+
+// Query text is tokenized or parsed once per search instead of once per sample
+struct PreparedQuery {
+    tokens: Vec<String>,
+    expr: Option<Result<QueryExpr, QueryParseError>>,
+    fuzzy: Option<SkimMatcherV2>,
+    drum_keywords: Vec<(DrumCategory, Vec<String>)>,
+    vocal_keywords: Vec<String>,
+    // Matched tokens missing here weigh 1
+    weights: HashMap<String, f32>,
+}
+
+impl PreparedQuery {
+    fn weigh_with(&mut self, index: &SearchIndex) {
+        let terms: Vec<String> = match &self.expr {
+            Some(Ok(expr)) => expr
+                .positive_terms()
+                .into_iter()
+                .map(|term| term.to_string())
+                .collect(),
+            _ => self.tokens.clone(),
+        };
+        for term in terms {
+            let weight = index.idf(&term);
+            self.weights.insert(term, weight);
+        }
+    }
+
+    fn weight(&self, token: &str) -> f32 {
+        self.weights.get(token).copied().unwrap_or(1.0)
+    }
+}
+
+fn prepare_query(query: &SearchParams) -> PreparedQuery {
+    let query_lowercase = query.query.to_lowercase();
+    PreparedQuery {
+        tokens: dedup_tokens(
+            split_query(&query_lowercase)
+                .into_iter()
+                .map(|token| token.to_string())
+                .collect(),
+        ),
+        expr: query
+            .boolean_query
+            .unwrap_or(false)
+            .then(|| parse_query_expr(&query.query)),
+        fuzzy: query.fuzzy.unwrap_or(false).then(SkimMatcherV2::default),
+        drum_keywords: drum_keywords_for(query),
+        vocal_keywords: vocal_keywords_for(query),
+        weights: HashMap::new(),
+    }
+}
+
+// Repeating a word doesn't score it twice, negated tokens are kept as typed
+fn dedup_tokens(tokens: Vec<String>) -> Vec<String> {
+    let mut seen: HashSet<String> = HashSet::new();
+    tokens
+        .into_iter()
+        .filter(|token| token.starts_with('-') || seen.insert(token.clone()))
+        .collect()
+}
+
+fn drum_keywords_for(query: &SearchParams) -> Vec<(DrumCategory, Vec<String>)> {
+    match query.drum_category {
+        Some(_) => query
+            .drum_keywords
+            .clone()
+            .unwrap_or_else(default_drum_keywords),
+        None => vec![],
+    }
+}
+
+fn vocal_keywords_for(query: &SearchParams) -> Vec<String> {
+    match query.vocal {
+        Some(_) => query
+            .vocal_keywords
+            .clone()
+            .unwrap_or_else(default_vocal_keywords),
+        None => vec![],
+    }
+}
+
+// Distance from the closest candidate tempo to low..=high, 0 inside the range
+fn tempo_distance(query: &SearchParams, low: u32, high: u32) -> Option<u32> {
+    let mut candidates: Vec<u32> = query.target_tempo.into_iter().collect();
+    if let Some(tempos) = &query.tempos {
+        candidates.extend(tempos);
+    }
+    if query.half_double_time.unwrap_or(false) {
+        let base = candidates.clone();
+        candidates.extend(base.iter().map(|t| t / 2));
+        candidates.extend(base.iter().map(|t| t.saturating_mul(2)));
+    }
+    candidates
+        .into_iter()
+        .map(|candidate| {
+            if candidate < low {
+                low - candidate
+            } else {
+                candidate.saturating_sub(high)
+            }
+        })
+        .min()
+}
+
+fn evaluate_relevance(
+    query: &SearchParams,
+    sample: &Sample,
+    prepared: &PreparedQuery,
+) -> RelevanceExplanation {
+    let mut explanation = RelevanceExplanation::default();
+
+    if let Some(sample_type) = &query.sample_type {
+        if std::mem::discriminant(sample_type) != std::mem::discriminant(&sample.sampletype) {
+            explanation.excluded_by_type = true;
+            return explanation;
+        }
+    }
+    if query.drum_category.is_some_and(|category| {
+        drum_category_with(sample, &prepared.drum_keywords) != Some(category)
+    }) {
+        explanation.excluded_by_type = true;
+        return explanation;
+    }
+    if query
+        .vocal
+        .is_some_and(|vocal| is_vocal_with(sample, &prepared.vocal_keywords) != vocal)
+    {
+        explanation.excluded_by_type = true;
+        return explanation;
+    }
+    if let Some(key) = &query.key {
+        if !detect_key(&sample.path).is_some_and(|sample_key| sample_key.is_enharmonic(key)) {
+            explanation.excluded_by_key = true;
+            return explanation;
+        }
+    }
+    if let Some(key) = &query.compatible_key {
+        if !detect_key(&sample.path).is_some_and(|sample_key| key.is_compatible(&sample_key)) {
+            explanation.excluded_by_key = true;
+            return explanation;
+        }
+    }
+    if let Some(sample_types) = &query.sample_types {
+        let discriminant = std::mem::discriminant(&sample.sampletype);
+        if !sample_types
+            .iter()
+            .any(|sample_type| std::mem::discriminant(sample_type) == discriminant)
+        {
+            explanation.excluded_by_type = true;
+            return explanation;
+        }
+    }
+
+    // The window applies to the sample's own tempo, one-shots are never tempo filtered
+    // A tempo range passes when it overlaps the window
+    if let SampleType::Loop(_) = sample.sampletype {
+        let tempo = sample.sampletype.tempo().unwrap_or(0);
+        let (low, high) = sample.tempo_range.unwrap_or((tempo, tempo));
+        if query.min_tempo.is_some_and(|min| high < min) {
+            trace!("{}: tempo {} below min_tempo", sample.path, high);
+            explanation.excluded_by_tempo = true;
+            return explanation;
+        }
+        if query.max_tempo.is_some_and(|max| low > max) {
+            trace!("{}: tempo {} above max_tempo", sample.path, low);
+            explanation.excluded_by_tempo = true;
+            return explanation;
+        }
+        explanation.tempo_distance = tempo_distance(query, low, high);
+        if explanation
+            .tempo_distance
+            .is_some_and(|distance| distance > query.tempo_tolerance.unwrap_or(0))
+        {
+            explanation.excluded_by_tempo = true;
+            return explanation;
+        }
+    }
+
+    if query.bars.is_some() && sample.bars != query.bars {
+        explanation.excluded_by_bars = true;
+        return explanation;
+    }
+
+    // Match against the full path so folder names are searchable, name is display only
+    let path_lowercase = sample.path_lowercase();
+    let mut base_score = 0.0;
+    match &prepared.expr {
+        Some(Ok(expr)) => {
+            for term in expr.positive_terms() {
+                if path_lowercase.contains(term) {
+                    explanation.matched_tokens.push(term.to_string());
+                }
+            }
+            if !expr.matches(&path_lowercase) {
+                return explanation;
+            }
+            // Pure exclusion queries like "NOT loop" still need a score to match
+            base_score = 1.0;
+        }
+        Some(Err(_)) => return explanation,
+        None => {
+            for token in &prepared.tokens {
+                if token.is_empty() {
+                    continue;
+                }
+                if token.starts_with('-') && path_lowercase.contains(&token.replace("-", "")) {
+                    explanation.negated_tokens.push(token.to_string());
+                }
+                let tag_hit = || {
+                    !token.starts_with('-')
+                        && query
+                            .field_weights
+                            .is_some_and(|weights| weights.tag_hit(sample, token))
+                };
+                if path_lowercase.contains(token.as_str()) || tag_hit() {
+                    explanation.matched_tokens.push(token.to_string());
+                } else if let Some(matcher) = &prepared.fuzzy {
+                    if !token.starts_with('-') && fuzzy_hit(matcher, &path_lowercase, token) {
+                        explanation.fuzzy_tokens.push(token.to_string());
+                    }
+                }
+            }
+        }
+    }
+
+    if explanation.negated_tokens.is_empty() {
+        let exact: f32 = explanation
+            .matched_tokens
+            .iter()
+            .map(|token| {
+                // Tag-only hits have no occurrences in the path to count
+                let occurrences = match path_lowercase.contains(token.as_str()) {
+                    true => occurrence_score(&path_lowercase, token, query.occurrence_decay),
+                    false => 1.0,
+                };
+                let field_weight = query.field_weights.map_or(1.0, |weights| {
+                    weights.weight_of(sample, &path_lowercase, token)
+                });
+                occurrences * field_weight * prepared.weight(token)
+            })
+            .sum();
+        let hits = exact + explanation.fuzzy_tokens.len() as f32;
+        explanation.score = hits.max(base_score);
+        // A tempo match counts as a hit so tempo-only searches return results
+        if explanation.tempo_distance.is_some() {
+            explanation.score += 1.0;
+        }
+        if explanation.score > 0.0 {
+            if let (Some(weight), Some(count)) = (query.usage_weight, sample.usage_count) {
+                explanation.score += count as f32 * weight;
+            }
+        }
+    }
+    explanation
+}
+
+pub fn use_sample_relevance(
+    query: &SearchParams,
+    sample: &Sample,
+    text_queries: &Vec<&str>,
+) -> i32 {
+    let prepared = PreparedQuery {
+        tokens: dedup_tokens(text_queries.iter().map(|token| token.to_string()).collect()),
+        expr: None,
+        fuzzy: None,
+        drum_keywords: drum_keywords_for(query),
+        vocal_keywords: vocal_keywords_for(query),
+        weights: HashMap::new(),
+    };
+    evaluate_relevance(query, sample, &prepared).score as i32
+}
+
+// Pack filtering happens per pack, use explain_sample to include it
+pub fn explain_relevance(query: &SearchParams, sample: &Sample) -> RelevanceExplanation {
+    evaluate_relevance(query, sample, &prepare_query(query))
+}
+
+pub fn explain_sample(
+    lib: &SampleLibrary,
+    query: &SearchParams,
+    path: &str,
+) -> Option<RelevanceExplanation> {
+    let (pack, sample) = find_with_pack(lib, path)?;
+    let mut explanation = explain_relevance(query, sample);
+    if query
+        .pack_id
+        .as_ref()
+        .is_some_and(|id| !pack.meta.name.eq(id))
+    {
+        explanation.excluded_by_pack = true;
+        explanation.score = 0.0;
+    }
+    Some(explanation)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParamError {
+    InvertedTempoRange { min: u32, max: u32 },
+    InvalidMaxResults(i32),
+    // A filter that is set but empty would match nothing, e.g. pack_id: Some("")
+    EmptyField(&'static str),
+    MalformedQuery(QueryParseError),
+}
+
+impl fmt::Display for ParamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParamError::InvertedTempoRange { min, max } => {
+                write!(f, "min_tempo {} is above max_tempo {}", min, max)
+            }
+            ParamError::InvalidMaxResults(n) => {
+                write!(f, "max_results must be positive, got {}", n)
+            }
+            ParamError::EmptyField(field) => write!(f, "{} is set but empty", field),
+            ParamError::MalformedQuery(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParamError {}
+
+// Catches parameters that would silently return nothing or something unexpected
+pub fn validate_params(query: &SearchParams) -> Result<(), ParamError> {
+    if let (Some(min), Some(max)) = (query.min_tempo, query.max_tempo) {
+        if min > max {
+            return Err(ParamError::InvertedTempoRange { min, max });
+        }
+    }
+    if let Some(max_results) = query.max_results {
+        if max_results <= 0 {
+            return Err(ParamError::InvalidMaxResults(max_results));
+        }
+    }
+    if query.pack_id.as_ref().is_some_and(|id| id.is_empty()) {
+        return Err(ParamError::EmptyField("pack_id"));
+    }
+    if query
+        .tempos
+        .as_ref()
+        .is_some_and(|tempos| tempos.is_empty())
+    {
+        return Err(ParamError::EmptyField("tempos"));
+    }
+    if query
+        .sample_types
+        .as_ref()
+        .is_some_and(|types| types.is_empty())
+    {
+        return Err(ParamError::EmptyField("sample_types"));
+    }
+    if query.boolean_query.unwrap_or(false) {
+        parse_query_expr(&query.query).map_err(ParamError::MalformedQuery)?;
+    }
+    Ok(())
+}
+
+// Negative max_results fall back to the default of 10 instead of wrapping around
+fn result_limit(query: &SearchParams) -> usize {
+    match query.max_results {
+        Some(input) if input >= 0 => input as usize,
+        _ => 10,
+    }
+}
+
+// Fuzzy-only matches go after every exact match, then score desc, then tempo distance asc
+type RankKey = (bool, Reverse<Score>, u32);
+
+// Relevance ordered with f32::total_cmp so it can be part of a sort key
+#[derive(Debug, Clone, Copy)]
+struct Score(f32);
+
+impl PartialEq for Score {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Score {}
+
+impl PartialOrd for Score {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Score {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+fn rank_key(explanation: &RelevanceExplanation) -> RankKey {
+    (
+        explanation.is_fuzzy_only(),
+        Reverse(Score(explanation.score)),
+        explanation.tempo_distance.unwrap_or(u32::MAX),
+    )
+}
+
+struct RankedMatch<'a> {
+    // Index into lib.packs
+    pack: usize,
+    sample: &'a Sample,
+    explanation: RelevanceExplanation,
+}
+
+// Best first, or shuffled when query.random is set
+fn order_matches<T>(
+    matches: &mut [T],
+    query: &SearchParams,
+    explanation: impl Fn(&T) -> &RelevanceExplanation,
+) {
+    if query.random.unwrap_or(false) {
+        let mut rng = match query.random_seed {
+            Some(seed) => ChaCha8Rng::seed_from_u64(seed),
+            None => ChaCha8Rng::from_entropy(),
+        };
+        matches.shuffle(&mut rng);
+    } else {
+        matches.sort_by_cached_key(|m| rank_key(explanation(m)));
+    }
+}
+
+// Every sample with relevance > 0, best first
+fn ranked_matches<'a>(lib: &'a SampleLibrary, query: &SearchParams) -> Vec<RankedMatch<'a>> {
+    match query.tf_idf.unwrap_or(false) {
+        true => ranked_matches_with(lib, query, Some(&SearchIndex::build(lib))),
+        false => ranked_matches_with(lib, query, None),
+    }
+}
+
+fn ranked_matches_with<'a>(
+    lib: &'a SampleLibrary,
+    query: &SearchParams,
+    index: Option<&SearchIndex>,
+) -> Vec<RankedMatch<'a>> {
+    let mut prepared = prepare_query(query);
+    if let Some(index) = index {
+        prepared.weigh_with(index);
+    }
+
+    let mut sorting_vec: Vec<RankedMatch> = vec![];
+    for (pack_index, pack) in lib.packs.iter().enumerate() {
+        if query.pack_id.is_some() {
+            if !pack.meta.name.eq(query.pack_id.as_ref().unwrap()) {
+                continue;
+            }
+        }
+
+        pack.samples.iter().for_each(|sample| {
+            let explanation = evaluate_relevance(query, sample, &prepared);
+            if explanation.score > 0.0 {
+                sorting_vec.push(RankedMatch {
+                    pack: pack_index,
+                    sample,
+                    explanation,
+                });
+            }
+        });
+    }
+
+    order_matches(&mut sorting_vec, query, |m| &m.explanation);
+    if let Some(max_per_pack) = query.max_per_pack {
+        let mut per_pack = vec![0; lib.packs.len()];
+        sorting_vec.retain(|m| {
+            per_pack[m.pack] += 1;
+            per_pack[m.pack] <= max_per_pack
+        });
+    }
+    sorting_vec
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PartitionedSearchResult {
+    pub exact: Vec<Sample>,
+    pub fuzzy: Vec<Sample>,
+}
+
+// The page search_lib would return, split into exact and fuzzy-only matches
+pub fn search_partitioned(lib: &SampleLibrary, query: &SearchParams) -> PartitionedSearchResult {
+    let mut result = PartitionedSearchResult {
+        exact: vec![],
+        fuzzy: vec![],
+    };
+    let offset = query.offset.unwrap_or(0);
+    for m in ranked_matches(lib, query)
+        .into_iter()
+        .skip(offset)
+        .take(result_limit(query))
+    {
+        if m.explanation.is_fuzzy_only() {
+            result.fuzzy.push(m.sample.clone());
+        } else {
+            result.exact.push(m.sample.clone());
+        }
+    }
+    result
+}
+
+// Positive query terms that matched no sample the search considered, in query order
+pub fn unmatched_terms(lib: &SampleLibrary, query: &SearchParams) -> Vec<String> {
+    let prepared = prepare_query(query);
+    let mut terms: Vec<String> = match &prepared.expr {
+        Some(Ok(expr)) => expr
+            .positive_terms()
+            .into_iter()
+            .map(|term| term.to_string())
+            .collect(),
+        Some(Err(_)) => return vec![],
+        None => prepared
+            .tokens
+            .iter()
+            .filter(|token| !token.starts_with('-'))
+            .cloned()
+            .collect(),
+    };
+    let mut seen = HashSet::new();
+    terms.retain(|term| seen.insert(term.clone()));
+
+    let mut hits: HashSet<String> = HashSet::new();
+    for pack in &lib.packs {
+        if query
+            .pack_id
+            .as_ref()
+            .is_some_and(|id| !pack.meta.name.eq(id))
+        {
+            continue;
+        }
+        for sample in &pack.samples {
+            let explanation = evaluate_relevance(query, sample, &prepared);
+            hits.extend(explanation.matched_tokens);
+            hits.extend(explanation.fuzzy_tokens);
+        }
+    }
+    terms.retain(|term| !hits.contains(term));
+    terms
+}
+
+pub fn search_lib(lib: &SampleLibrary, query: &SearchParams) -> SearchResult {
+    search_paged(lib, query).into()
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PagedSearchResult {
+    pub samples: Vec<Sample>,
+    pub total_matches: usize,
+    pub offset: usize,
+}
+
+impl From<PagedSearchResult> for SearchResult {
+    fn from(paged: PagedSearchResult) -> Self {
+        SearchResult {
+            samples: paged.samples,
+        }
+    }
+}
+
+// Combined searches return as many samples as the most generous query asks for
+fn combined_limit(queries: &[SearchParams]) -> usize {
+    queries.iter().map(result_limit).max().unwrap_or(0)
+}
+
+// Samples matching any query, each once with its best ranking across queries.
+// Offsets and random ordering of the individual queries are ignored.
+pub fn search_union(lib: &SampleLibrary, queries: &[SearchParams]) -> SearchResult {
+    let mut best: HashMap<&str, RankedMatch> = HashMap::new();
+    for query in queries {
+        for m in ranked_matches(lib, query) {
+            match best.get(m.sample.path.as_str()) {
+                Some(kept) if rank_key(&kept.explanation) <= rank_key(&m.explanation) => {}
+                _ => {
+                    best.insert(m.sample.path.as_str(), m);
+                }
+            }
+        }
+    }
+    let mut matches: Vec<RankedMatch> = best.into_values().collect();
+    matches.sort_by_cached_key(|m| (rank_key(&m.explanation), m.sample.path.clone()));
+    SearchResult {
+        samples: matches
+            .into_iter()
+            .take(combined_limit(queries))
+            .map(|m| m.sample.clone())
+            .collect(),
+    }
+}
+
+// Samples matching every query. Relevance is the sum over queries, the tempo distance
+// the closest one, and a sample is fuzzy-only when it has no exact token in any query.
+pub fn search_intersection(lib: &SampleLibrary, queries: &[SearchParams]) -> SearchResult {
+    let Some((first, rest)) = queries.split_first() else {
+        return SearchResult { samples: vec![] };
+    };
+    let mut combined: HashMap<&str, RankedMatch> = ranked_matches(lib, first)
+        .into_iter()
+        .map(|m| (m.sample.path.as_str(), m))
+        .collect();
+    for query in rest {
+        let mut next = HashMap::new();
+        for m in ranked_matches(lib, query) {
+            if let Some(mut kept) = combined.remove(m.sample.path.as_str()) {
+                let explanation = &mut kept.explanation;
+                explanation.score += m.explanation.score;
+                explanation
+                    .matched_tokens
+                    .extend(m.explanation.matched_tokens);
+                explanation.fuzzy_tokens.extend(m.explanation.fuzzy_tokens);
+                explanation.tempo_distance =
+                    match (explanation.tempo_distance, m.explanation.tempo_distance) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                next.insert(m.sample.path.as_str(), kept);
+            }
+        }
+        combined = next;
+    }
+    let mut matches: Vec<RankedMatch> = combined.into_values().collect();
+    matches.sort_by_cached_key(|m| (rank_key(&m.explanation), m.sample.path.clone()));
+    SearchResult {
+        samples: matches
+            .into_iter()
+            .take(combined_limit(queries))
+            .map(|m| m.sample.clone())
+            .collect(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SourcedSample {
+    // Name of the SampleLibrary the sample belongs to
+    pub library: String,
+    pub sample: Sample,
+}
+
+// One ranking over several libraries, ties keep the order of libs
+pub fn search_multi_sourced(libs: &[&SampleLibrary], query: &SearchParams) -> Vec<SourcedSample> {
+    let mut matches: Vec<(&SampleLibrary, RankedMatch)> = vec![];
+    for lib in libs {
+        matches.extend(ranked_matches(lib, query).into_iter().map(|m| (*lib, m)));
+    }
+    order_matches(&mut matches, query, |(_, m)| &m.explanation);
+    matches
+        .into_iter()
+        .skip(query.offset.unwrap_or(0))
+        .take(result_limit(query))
+        .map(|(lib, m)| SourcedSample {
+            library: lib.name.clone(),
+            sample: m.sample.clone(),
+        })
+        .collect()
+}
+
+pub fn search_multi(libs: &[&SampleLibrary], query: &SearchParams) -> SearchResult {
+    SearchResult {
+        samples: search_multi_sourced(libs, query)
+            .into_iter()
+            .map(|sourced| sourced.sample)
+            .collect(),
+    }
+}
+
+// Every match of query as a pack, best first. max_results and offset are ignored,
+// meta.source_query holds the search so the pack can be rebuilt later.
+pub fn smart_pack(lib: &SampleLibrary, query: &SearchParams, name: &str) -> Pack {
+    let mut pack = Pack {
+        samples: ranked_matches(lib, query)
+            .into_iter()
+            .map(|m| m.sample.clone())
+            .collect(),
+        meta: PackInfo {
+            description: format!("Samples matching \"{}\"", query.query),
+            name: name.to_string(),
+            img: None,
+            num_samples: None,
+            source_query: Some(query.clone()),
+        },
+    };
+    refresh_count(&mut pack);
+    pack
+}
+
+// Same as search_paged(..).total_matches without cloning or sorting anything
+pub fn count_matches(lib: &SampleLibrary, query: &SearchParams) -> usize {
+    let prepared = prepare_query(query);
+    lib.packs
+        .iter()
+        .filter(|pack| {
+            query
+                .pack_id
+                .as_ref()
+                .is_none_or(|id| pack.meta.name.eq(id))
+        })
+        .map(|pack| {
+            let matches = pack
+                .samples
+                .iter()
+                .filter(|sample| evaluate_relevance(query, sample, &prepared).score > 0.0)
+                .count();
+            matches.min(query.max_per_pack.unwrap_or(usize::MAX))
+        })
+        .sum()
+}
+
+// Counts every match, then returns max_results of them starting at offset
+pub fn search_paged(lib: &SampleLibrary, query: &SearchParams) -> PagedSearchResult {
+    let matches = ranked_matches(lib, query);
+    let offset = query.offset.unwrap_or(0);
+    PagedSearchResult {
+        total_matches: matches.len(),
+        samples: matches
+            .into_iter()
+            .skip(offset)
+            .take(result_limit(query))
+            .map(|m| m.sample.clone())
+            .collect(),
+        offset,
+    }
+}
+
+// tf_idf scoring from an index built once, instead of once per search.
+// The index is used whether or not the query sets tf_idf.
+pub fn search_with_index(
+    lib: &SampleLibrary,
+    index: &SearchIndex,
+    query: &SearchParams,
+) -> SearchResult {
+    SearchResult {
+        samples: ranked_matches_with(lib, query, Some(index))
+            .into_iter()
+            .skip(query.offset.unwrap_or(0))
+            .take(result_limit(query))
+            .map(|m| m.sample.clone())
+            .collect(),
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct SearchResponse {
+    pub samples: Vec<Sample>,
+    pub total_matches: usize,
+    pub offset: usize,
+    pub max_results: usize,
+    // Lowercased query tokens joined by single spaces
+    pub query: String,
+}
+
+pub fn search_response(lib: &SampleLibrary, query: &SearchParams) -> SearchResponse {
+    let paged = search_paged(lib, query);
+    SearchResponse {
+        samples: paged.samples,
+        total_matches: paged.total_matches,
+        offset: paged.offset,
+        max_results: result_limit(query),
+        query: split_query(&query.query.to_lowercase()).join(" "),
+    }
+}
+
+// The page search_lib would return grouped by loop tempo, ranked within each group.
+// One-shots and loops without a tempo go under 0 with include_untimed, else they're dropped.
+pub fn search_by_tempo(
+    lib: &SampleLibrary,
+    query: &SearchParams,
+    include_untimed: bool,
+) -> BTreeMap<u32, Vec<Sample>> {
+    let mut groups: BTreeMap<u32, Vec<Sample>> = BTreeMap::new();
+    for sample in search_lib(lib, query).samples {
+        match sample.sampletype.tempo() {
+            Some(tempo) => groups.entry(tempo).or_default().push(sample),
+            None if include_untimed => groups.entry(0).or_default().push(sample),
+            None => {}
+        }
+    }
+    groups
+}
+
+// One Sample per line, each line a complete JSON document
+pub fn result_to_ndjson(result: &SearchResult) -> String {
+    result
+        .samples
+        .iter()
+        .map(|sample| serde_json::to_string(sample).expect("Couldnt create json data!") + "\n")
+        .collect()
+}
+
+// Streams result_to_ndjson, wrap the writer in a BufWriter for files and sockets
+pub fn write_result_ndjson(result: &SearchResult, mut writer: impl Write) -> Result<(), LibError> {
+    for sample in &result.samples {
+        serde_json::to_writer(&mut writer, sample)?;
+        writer.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "decentsampler")]
+fn xml_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+// Minimal DecentSampler preset, one key per sample upwards from C1 (MIDI note 36).
+// Samples past note 127 are left out.
+#[cfg(feature = "decentsampler")]
+pub fn result_to_decentsampler(result: &SearchResult) -> String {
+    let mut xml = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xml += "<DecentSampler minVersion=\"1.0.0\">\n  <groups>\n    <group>\n";
+    for (sample, note) in result.samples.iter().zip(36..=127) {
+        xml += &format!(
+            "      <sample path=\"{}\" name=\"{}\" rootNote=\"{note}\" loNote=\"{note}\" hiNote=\"{note}\"/>\n",
+            xml_escape(&sample.path),
+            xml_escape(&sample.name),
+        );
+    }
+    xml += "    </group>\n  </groups>\n</DecentSampler>\n";
+    xml
+}
+
+// "120 | Loop | name | path", one-shots show "-" in the tempo column
+pub fn format_sample_line(sample: &Sample) -> String {
+    let (tempo, kind) = match sample.sampletype {
+        SampleType::Loop(tempo) => (tempo.to_string(), "Loop"),
+        SampleType::OneShot => ("-".to_string(), "OneShot"),
+    };
+    format!("{} | {} | {} | {}", tempo, kind, sample.name, sample.path)
+}
+
+pub fn format_result(result: &SearchResult) -> String {
+    result
+        .samples
+        .iter()
+        .map(format_sample_line)
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Where a tempo candidate was found, in order of priority
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TempoSource {
+    Bracket,
+    Bpm,
+    Bare,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq)]
+pub struct TempoGuess {
+    pub tempo: Option<u32>,
+    pub source: Option<TempoSource>,
+    // Every other distinct candidate, best first
+    pub alternates: Vec<u32>,
+}
+
+// Numbers in 40..=300 are candidates: "[120]" beats "128bpm" / "128 bpm" beats a bare "90".
+// Candidates from the same source keep the order they appear in. Bpm and bare
+// numbers only count in the file name, folders like "Vol 100" often hold other numbers.
+pub fn resolve_tempo(path: &str) -> TempoGuess {
+    let lower = path.to_lowercase();
+    let bytes = lower.as_bytes();
+    let name_start = lower.rfind(['/', '\\']).map_or(0, |index| index + 1);
+    let mut candidates: Vec<(TempoSource, u32)> = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        if !bytes[i].is_ascii_digit() {
+            i += 1;
+            continue;
+        }
+        let start = i;
+        while i < bytes.len() && bytes[i].is_ascii_digit() {
+            i += 1;
+        }
+        let Ok(tempo) = lower[start..i].parse::<u32>() else {
+            continue;
+        };
+        if !(40..=300).contains(&tempo) {
+            continue;
+        }
+        let after = lower[i..].trim_start_matches([' ', '_', '-']);
+        let source = if start > 0 && bytes[start - 1] == b'[' && bytes.get(i) == Some(&b']') {
+            TempoSource::Bracket
+        } else if start < name_start {
+            continue;
+        } else if after.starts_with("bpm") {
+            TempoSource::Bpm
+        } else {
+            TempoSource::Bare
+        };
+        candidates.push((source, tempo));
+    }
+    candidates.sort_by_key(|(source, _)| *source);
+
+    let mut guess = TempoGuess::default();
+    for (source, tempo) in candidates {
+        if guess.tempo.is_none() {
+            guess.tempo = Some(tempo);
+            guess.source = Some(source);
+        } else if guess.tempo != Some(tempo) && !guess.alternates.contains(&tempo) {
+            guess.alternates.push(tempo);
+        }
+    }
+    guess
+}
+
+// Bracket and bpm tempos first, then the low end of a range like "120-124", then bare numbers
+fn detect_tempo_txt(path: &str) -> i32 {
+    let guess = resolve_tempo(path);
+    if let (Some(tempo), Some(TempoSource::Bracket | TempoSource::Bpm)) =
+        (guess.tempo, guess.source)
+    {
+        return tempo as i32;
+    }
+    if let Some((low, _)) = detect_tempo_range(path) {
+        return low as i32;
+    }
+    guess.tempo.map_or(0, |tempo| tempo as i32)
+}
+
+// "groove_120-124.wav" -> (120, 124). Both ends must lie in 40..=300 and low < high,
+// which keeps take numbers like "kick_01-02" and years like "2019-2020" out.
+// Only the file name is scanned, folder names like "Vol 100-200" are ignored.
+pub fn detect_tempo_range(path: &str) -> Option<(u32, u32)> {
+    let file_name = file_name_of(path);
+    let path = file_name.as_str();
+    for (index, _) in path.match_indices('-') {
+        let start = path[..index]
+            .trim_end_matches(|c: char| c.is_ascii_digit())
+            .len();
+        let end = index
+            + 1
+            + path[index + 1..]
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(path.len() - index - 1);
+        if start == index || end == index + 1 {
+            continue;
+        }
+        let (Ok(low), Ok(high)) = (
+            path[start..index].parse::<u32>(),
+            path[index + 1..end].parse::<u32>(),
+        ) else {
+            continue;
+        };
+        if (40..=300).contains(&low) && (40..=300).contains(&high) && low < high {
+            return Some((low, high));
+        }
+    }
+    None
+}
+
+fn detect_type(path: &str) -> SampleType {
+    let loop_signals = [
+        "/loop",
+        "/construction",
+        "_loop",
+        "[",
+        "bpm",
+        "loop",
+        "loops",
+    ];
+    // todo: load keywords from json
+    let path_lower = path.to_lowercase();
+    for keyword in loop_signals {
+        if path_lower.contains(keyword) {
+            let tempo = detect_tempo_txt(&path_lower);
+            return SampleType::Loop(tempo);
+        }
+    }
+    SampleType::OneShot
+}
+
+// Length of a loop in bars, None when any input is zero
+pub fn loop_bars(tempo: u32, duration_secs: f32, beats_per_bar: u32) -> Option<f32> {
+    if tempo == 0 || beats_per_bar == 0 || duration_secs <= 0.0 {
+        return None;
+    }
+    Some(duration_secs * tempo as f32 / 60.0 / beats_per_bar as f32)
+}
+
+// None for one-shots and loops with unknown tempo or duration
+pub fn sample_loop_bars(sample: &Sample, beats_per_bar: u32) -> Option<f32> {
+    loop_bars(
+        sample.sampletype.tempo()?,
+        sample.duration_secs?,
+        beats_per_bar,
+    )
+}
+
+// Samples detection probably got wrong, with the reason. Loop length is checked
+// in 4/4 and only for samples that have a duration.
+pub fn classification_warnings(lib: &SampleLibrary) -> Vec<(Sample, String)> {
+    let mut warnings = vec![];
+    for sample in all_samples(lib) {
+        let reason = match sample.sampletype {
+            SampleType::OneShot if sample.name.to_lowercase().contains("loop") => {
+                Some("one-shot named like a loop")
+            }
+            SampleType::Loop(_) if sample.sampletype.tempo().is_none() => {
+                Some("loop without a detectable tempo")
+            }
+            SampleType::Loop(_) if sample_loop_bars(sample, 4).is_some_and(|bars| bars < 1.0) => {
+                Some("loop shorter than one bar")
+            }
+            _ => None,
+        };
+        if let Some(reason) = reason {
+            warnings.push((sample.clone(), reason.to_string()));
+        }
+    }
+    warnings
+}
+
+pub fn round_to_quarter_bar(bars: f32) -> f32 {
+    (bars * 4.0).round() / 4.0
+}
+
+// Doubles or halves tempo until it lies in low..=high. Tempos that can't land
+// in the band (0, a band narrower than an octave or one too high to double into)
+// are returned unchanged.
+pub fn normalize_tempo(tempo: u32, low: u32, high: u32) -> u32 {
+    if tempo == 0 || low == 0 {
+        return tempo;
+    }
+    let mut normalized = tempo;
+    while normalized < low {
+        match normalized.checked_mul(2) {
+            Some(doubled) => normalized = doubled,
+            None => return tempo,
+        }
+    }
+    while normalized > high {
+        normalized /= 2;
+    }
+    if normalized < low {
+        return tempo;
+    }
+    normalized
+}
+
+fn folder_type(path: &str, opts: &LoadOptions) -> Option<SampleType> {
+    let path_lower = path.to_lowercase();
+    let is_named =
+        |folders: &Vec<String>, dir: &str| folders.iter().any(|f| f.to_lowercase() == dir);
+    // Skip the file name, walk folders from the innermost one
+    for dir in path_lower.rsplit(['/', '\\']).skip(1) {
+        if is_named(&opts.oneshot_folders, dir) {
+            return Some(SampleType::OneShot);
+        }
+        if is_named(&opts.loop_folders, dir) {
+            return Some(SampleType::Loop(detect_tempo_txt(&path_lower)));
+        }
+    }
+    None
+}
+
+fn detect_type_with_options(path: &str, opts: &LoadOptions) -> SampleType {
+    let mut stype = folder_type(path, opts).unwrap_or_else(|| detect_type(path));
+    if let Some(sanitize) = &opts.tempo_sanitize {
+        stype = sanitize.apply(stype);
+    }
+    if let (SampleType::Loop(tempo), Some((low, high))) = (&mut stype, opts.normalize_tempo) {
+        *tempo = normalize_tempo(*tempo as u32, low, high) as i32;
+    }
+    stype
+}
+
+// Matches "4bars", "4 bars", "1_bar" and "8beats", beat counts assume 4/4.
+// Only the file name is scanned, a folder like "Loops 8 Bars" holds other lengths too.
+pub fn detect_bars(path: &str) -> Option<u32> {
+    let path_lower = file_name_of(path).to_lowercase();
+    for (unit, beats_per_unit) in [("bar", 4), ("beat", 1)] {
+        for (index, _) in path_lower.match_indices(unit) {
+            let rest = &path_lower[index + unit.len()..];
+            let rest = rest.strip_prefix('s').unwrap_or(rest);
+            if rest.starts_with(|c: char| c.is_alphabetic()) {
+                continue;
+            }
+            let before = &path_lower[..index];
+            let before = before.strip_suffix([' ', '_']).unwrap_or(before);
+            let digits_start = before.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+            let beats = match before[digits_start..].parse::<u32>() {
+                Ok(count) => match count.checked_mul(beats_per_unit) {
+                    Some(beats) => beats,
+                    None => continue,
+                },
+                Err(_) => continue,
+            };
+            if beats > 0 && beats % 4 == 0 {
+                return Some(beats / 4);
+            }
+        }
+    }
+    None
+}
+
+fn file_name_of(path: &str) -> String {
+    path.rsplit(['/', '\\']).next().unwrap_or(path).to_string()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateError(pub String);
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid name template: {}", self.0)
+    }
+}
+
+impl std::error::Error for TemplateError {}
+
+enum TemplatePart {
+    Text(String),
+    Type,
+    Tempo,
+    Name,
+    Pack,
+}
+
+fn parse_name_template(template: &str) -> Result<Vec<TemplatePart>, TemplateError> {
+    let mut parts = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        if start > 0 {
+            parts.push(TemplatePart::Text(rest[..start].to_string()));
+        }
+        let Some(end) = rest[start..].find('}') else {
+            return Err(TemplateError(format!("unclosed \"{}\"", &rest[start..])));
+        };
+        let placeholder = &rest[start + 1..start + end];
+        parts.push(match placeholder {
+            "type" => TemplatePart::Type,
+            "tempo" => TemplatePart::Tempo,
+            "name" => TemplatePart::Name,
+            "pack" => TemplatePart::Pack,
+            _ => {
+                return Err(TemplateError(format!(
+                    "unknown placeholder {{{}}}",
+                    placeholder
+                )))
+            }
+        });
+        rest = &rest[start + end + 1..];
+    }
+    if !rest.is_empty() {
+        parts.push(TemplatePart::Text(rest.to_string()));
+    }
+    Ok(parts)
+}
+
+// Sets every sample name from a template like "{type}_{tempo}_{name}", files are not renamed.
+// {type} is loop or oneshot, {tempo} is empty without a tempo and {name} is the
+// file name, so applying a template again doesn't nest the old result.
+pub fn apply_name_template(lib: &mut SampleLibrary, template: &str) -> Result<(), TemplateError> {
+    let parts = parse_name_template(template)?;
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            let mut name = String::new();
+            for part in &parts {
+                match part {
+                    TemplatePart::Text(text) => name.push_str(text),
+                    TemplatePart::Type => name.push_str(match sample.sampletype {
+                        SampleType::Loop(_) => "loop",
+                        SampleType::OneShot => "oneshot",
+                    }),
+                    TemplatePart::Tempo => {
+                        if let Some(tempo) = sample.sampletype.tempo() {
+                            name.push_str(&tempo.to_string());
+                        }
+                    }
+                    TemplatePart::Name => name.push_str(&file_name_of(&sample.path)),
+                    TemplatePart::Pack => name.push_str(&pack.meta.name),
+                }
+            }
+            sample.name = name;
+        }
+    }
+    Ok(())
+}
+
+// Path caches are never saved, call this after loading a library to fill them
+pub fn build_path_cache(lib: &mut SampleLibrary) {
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            sample.refresh_path_cache();
+        }
+    }
+}
+
+// Repairs libraries saved while name still held the full path
+pub fn refresh_names(lib: &mut SampleLibrary) {
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            sample.name = file_name_of(&sample.path);
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Default, PartialEq, Eq)]
+pub struct TagApplyReport {
+    // Samples that received tags
+    pub tagged: usize,
+    // Paths in the tag file that matched no sample
+    pub unknown_paths: usize,
+}
+
+// tags_path holds a JSON object of sample path -> list of tags. Tags a sample already
+// has aren't added twice.
+pub fn apply_tag_file(
+    lib: &mut SampleLibrary,
+    tags_path: &str,
+) -> Result<TagApplyReport, LibError> {
+    let content = std::fs::read_to_string(tags_path)?;
+    let mut tag_map: HashMap<String, Vec<String>> = serde_json::from_str(&content)?;
+    let mut tagged = 0;
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            if let Some(tags) = tag_map.remove(&sample.path) {
+                for tag in tags {
+                    if !sample.tags.contains(&tag) {
+                        sample.tags.push(tag);
+                    }
+                }
+                tagged += 1;
+            }
+        }
+    }
+    Ok(TagApplyReport {
+        tagged,
+        unknown_paths: tag_map.len(),
+    })
+}
+
+// Overridden samples lose their detected tempo_range so the given tempo is the one filtered on
+pub fn apply_type_overrides(lib: &mut SampleLibrary, overrides: &HashMap<String, SampleType>) {
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            if let Some(sampletype) = overrides.get(&sample.path) {
+                sample.sampletype = sampletype.clone();
+                sample.tempo_range = None;
+                sample.type_overridden = true;
+            }
+        }
+    }
+}
+
+// Re-runs type detection from the paths without touching the disk and returns how
+// many samples changed type. Types set by apply_type_overrides are kept.
+pub fn reclassify_if_options_changed(lib: &mut SampleLibrary, opts: &LoadOptions) -> usize {
+    let mut changed = 0;
+    for pack in &mut lib.packs {
+        for sample in &mut pack.samples {
+            if sample.type_overridden {
+                continue;
+            }
+            let sampletype = detect_type_with_options(&sample.path, opts);
+            if sampletype != sample.sampletype {
+                trace!("{}: reclassified as {:?}", sample.path, sampletype);
+                sample.sampletype = sampletype;
+                sample.tempo_range = detect_tempo_range(&sample.path);
+                changed += 1;
+            }
+        }
+    }
+    changed
+}
+
+fn sample_from_path(path: &str, opts: &LoadOptions) -> Sample {
+    let mut sample = Sample {
+        name: file_name_of(path),
+        path: path.to_string(),
+        sampletype: detect_type_with_options(path, opts),
+        bars: detect_bars(path),
+        modified: std::fs::metadata(path).and_then(|m| m.modified()).ok(),
+        duration_secs: read_duration(path, opts),
+        usage_count: None,
+        tempo_range: detect_tempo_range(path),
+        tags: vec![],
+        effective_duration_secs: read_effective_duration(path, opts),
+        type_overridden: false,
+        path_cache: PathCache::default(),
+    };
+    if opts.cache_paths {
+        sample.refresh_path_cache();
+    }
+    sample
+}
+
+pub fn get_sample(path: &str) -> Sample {
+    sample_from_path(path, &LoadOptions::default())
+}
+
+pub fn load_pack(path: &str, name: &str, desc: &str) -> Pack {
+    load_pack_with_options(path, name, desc, &LoadOptions::default())
+}
+
+pub fn load_pack_with_options(path: &str, name: &str, desc: &str, opts: &LoadOptions) -> Pack {
+    load_pack_report(path, name, desc, opts).0
+}
+
+// Calls f with every (interleaved) value of a WAV scaled to -1.0..=1.0
+#[cfg(feature = "audio")]
+fn for_each_wav_value(path: &str, mut f: impl FnMut(f32)) -> Result<hound::WavSpec, LibError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    match spec.sample_format {
+        hound::SampleFormat::Float => {
+            for value in reader.samples::<f32>() {
+                f(value?);
+            }
+        }
+        hound::SampleFormat::Int => {
+            let full_scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+            for value in reader.samples::<i32>() {
+                f(value? as f32 / full_scale);
+            }
+        }
+    }
+    Ok(spec)
+}
+
+#[cfg(feature = "audio")]
+fn wav_peak(path: &str) -> Result<Option<f32>, LibError> {
+    let mut peak: Option<f32> = None;
+    for_each_wav_value(path, |value| {
+        peak = Some(peak.unwrap_or(0.0).max(value.abs()))
+    })?;
+    Ok(peak)
+}
+
+// Over all channels, dBFS is -inf for silence. LUFS would need K-weighting
+// and gating and isn't measured.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct LoudnessInfo {
+    pub rms: f32,
+    pub peak: f32,
+    pub rms_dbfs: f32,
+    pub peak_dbfs: f32,
+}
+
+#[cfg(feature = "audio")]
+pub fn measure_loudness(path: &str) -> Result<LoudnessInfo, LibError> {
+    let mut sum_squares = 0.0f64;
+    let mut values = 0u64;
+    let mut peak = 0.0f32;
+    for_each_wav_value(path, |value| {
+        sum_squares += (value as f64).powi(2);
+        values += 1;
+        peak = peak.max(value.abs());
+    })?;
+    let rms = if values == 0 {
+        0.0
+    } else {
+        (sum_squares / values as f64).sqrt() as f32
+    };
+    Ok(LoudnessInfo {
+        rms,
+        peak,
+        rms_dbfs: 20.0 * rms.log10(),
+        peak_dbfs: 20.0 * peak.log10(),
+    })
+}
+
+// WAV samples whose RMS level is more than tolerance_db away from the library's median.
+// Files that can't be measured are left out.
+#[cfg(feature = "audio")]
+pub fn loudness_outliers(lib: &SampleLibrary, tolerance_db: f32) -> Vec<(&Sample, LoudnessInfo)> {
+    let measured: Vec<(&Sample, LoudnessInfo)> = all_samples(lib)
+        .filter(|sample| extension_of(&sample.path).as_deref() == Some("wav"))
+        .filter_map(|sample| Some((sample, measure_loudness(&sample.path).ok()?)))
+        .collect();
+    let mut levels: Vec<f32> = measured.iter().map(|(_, info)| info.rms_dbfs).collect();
+    if levels.is_empty() {
+        return vec![];
+    }
+    levels.sort_by(|a, b| a.total_cmp(b));
+    let median = levels[levels.len() / 2];
+    measured
+        .into_iter()
+        .filter(|(_, info)| (info.rms_dbfs - median).abs() > tolerance_db)
+        .collect()
+}
+
+// correlation is None for files that aren't stereo or are silent. Below 0 the
+// channels partly cancel when summed to mono, -1 is fully out of phase.
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+pub struct StereoInfo {
+    pub channels: u16,
+    pub dual_mono: bool,
+    pub correlation: Option<f32>,
+}
+
+#[cfg(feature = "audio")]
+pub fn stereo_analysis(path: &str) -> Result<StereoInfo, LibError> {
+    let channels = hound::WavReader::open(path)?.spec().channels;
+    if channels != 2 {
+        return Ok(StereoInfo {
+            channels,
+            dual_mono: false,
+            correlation: None,
+        });
+    }
+    let mut left: Option<f32> = None;
+    let mut identical = true;
+    let (mut sum_lr, mut sum_ll, mut sum_rr) = (0.0f64, 0.0f64, 0.0f64);
+    for_each_wav_value(path, |value| match left.take() {
+        None => left = Some(value),
+        Some(l) => {
+            identical &= l == value;
+            sum_lr += l as f64 * value as f64;
+            sum_ll += (l as f64).powi(2);
+            sum_rr += (value as f64).powi(2);
+        }
+    })?;
+    let energy = (sum_ll * sum_rr).sqrt();
+    Ok(StereoInfo {
+        channels,
+        dual_mono: identical,
+        correlation: (energy > 0.0).then(|| (sum_lr / energy) as f32),
+    })
+}
+
+// Stereo WAVs whose channels are identical and could be stored as mono
+#[cfg(feature = "audio")]
+pub fn dual_mono_samples(lib: &SampleLibrary) -> Vec<&Sample> {
+    all_samples(lib)
+        .filter(|sample| extension_of(&sample.path).as_deref() == Some("wav"))
+        .filter(|sample| stereo_analysis(&sample.path).is_ok_and(|info| info.dual_mono))
+        .collect()
+}
+
+#[cfg(feature = "audio")]
+fn read_duration(path: &str, opts: &LoadOptions) -> Option<f32> {
+    if !opts.read_headers || extension_of(path).as_deref() != Some("wav") {
+        return None;
+    }
+    let reader = hound::WavReader::open(path).ok()?;
+    Some(reader.duration() as f32 / reader.spec().sample_rate as f32)
+}
+
+#[cfg(not(feature = "audio"))]
+fn read_duration(_path: &str, _opts: &LoadOptions) -> Option<f32> {
+    None
+}
+
+// Seconds from the first to the last frame with any channel above threshold_db (dBFS),
+// 0.0 when nothing is that loud
+#[cfg(feature = "audio")]
+pub fn effective_duration(path: &str, threshold_db: f32) -> Result<f32, LibError> {
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let mut index = 0usize;
+    let mut first = None;
+    let mut last = 0;
+    let spec = for_each_wav_value(path, |value| {
+        if value.abs() > threshold {
+            first.get_or_insert(index);
+            last = index;
+        }
+        index += 1;
+    })?;
+    let Some(first) = first else {
+        return Ok(0.0);
+    };
+    let channels = spec.channels.max(1) as usize;
+    let frames = last / channels - first / channels + 1;
+    Ok(frames as f32 / spec.sample_rate as f32)
+}
+
+#[cfg(feature = "audio")]
+fn read_effective_duration(path: &str, opts: &LoadOptions) -> Option<f32> {
+    let threshold_db = opts.trim_threshold_db?;
+    if extension_of(path).as_deref() != Some("wav") {
+        return None;
+    }
+    effective_duration(path, threshold_db).ok()
+}
+
+#[cfg(not(feature = "audio"))]
+fn read_effective_duration(_path: &str, _opts: &LoadOptions) -> Option<f32> {
+    None
+}
+
+fn is_blocked_path(path: &str, opts: &LoadOptions) -> bool {
+    let path_lower = path.to_lowercase();
+    let contains = |folder: &String| path_lower.contains(&folder.to_lowercase());
+    opts.blocked_folders.iter().any(contains)
+        || (!opts.allowed_folders.is_empty() && !opts.allowed_folders.iter().any(contains))
+}
+
+fn is_preview_file(file_name: &str, opts: &LoadOptions) -> bool {
+    let tokens = path_tokens(file_name);
+    let is_pattern = |pattern: &str| tokens.iter().any(|token| is_keyword_token(token, pattern));
+    match &opts.preview_patterns {
+        Some(patterns) => patterns.iter().any(|pattern| is_pattern(pattern)),
+        None => DEFAULT_PREVIEW_PATTERNS
+            .iter()
+            .any(|pattern| is_pattern(pattern)),
+    }
+}
+
+fn is_silent_file(path: &str, opts: &LoadOptions) -> bool {
+    if !opts.skip_silent {
+        return false;
+    }
+    if std::fs::metadata(path).is_ok_and(|m| m.len() == 0) {
+        return true;
+    }
+    #[cfg(feature = "audio")]
+    if extension_of(path).as_deref() == Some("wav") {
+        if let Ok(peak) = wav_peak(path) {
+            return peak.is_none_or(|peak| peak <= opts.silence_threshold);
+        }
+    }
+    false
+}
+
+// Like load_pack_with_options for hand-picked files, in the given order.
+// Files load_pack would skip are left out.
+pub fn pack_from_paths(paths: &[&str], name: &str, desc: &str, opts: &LoadOptions) -> Pack {
+    let mut pack = Pack {
+        samples: vec![],
+        meta: PackInfo {
+            description: desc.to_string(),
+            name: name.to_string(),
+            img: None,
+            num_samples: None,
+            source_query: None,
+        },
+    };
+    for path in paths {
+        if is_skipped_file(path, opts) {
+            trace!("Skipping file: {}", path);
+            continue;
+        }
+        pack.samples.push(sample_from_path(path, opts));
+    }
+    refresh_count(&mut pack);
+    pack
+}
+
+// The checks load_pack skips files with, for a single path
+fn is_skipped_file(path: &str, opts: &LoadOptions) -> bool {
+    let file_name = file_name_of(path);
+    !is_sample_file_name(&file_name)
+        || is_blocked_path(path, opts)
+        || is_preview_file(&file_name, opts)
+        || is_silent_file(path, opts)
+}
+
+fn is_sample_file_name(entry_name: &str) -> bool {
+    entry_name.contains(".wav") || entry_name.contains(".mp3")
+}
+
+// What load_pack_report would pick up, from file metadata only.
+// Silent files can't be told apart without reading them, so they are counted.
+pub fn estimate_pack_scan(path: &str, opts: &LoadOptions) -> ScanEstimate {
+    let mut estimate = ScanEstimate::default();
+    for entry in WalkDir::new(path)
+        .follow_links(opts.follow_links)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        let entry_path = entry.path().display().to_string();
+        let entry_name = entry.file_name().to_string_lossy();
+        if !is_sample_file_name(&entry_name)
+            || is_blocked_path(&entry_path, opts)
+            || is_preview_file(&entry_name, opts)
+        {
+            continue;
+        }
+        estimate.files += 1;
+        estimate.total_bytes += entry.metadata().map_or(0, |m| m.len());
+    }
+    estimate
+}
+
+pub fn load_pack_report(
+    path: &str,
+    name: &str,
+    desc: &str,
+    opts: &LoadOptions,
+) -> (Pack, LoadReport) {
+    scan_pack(path, name, desc, opts, None)
+}
+
+// Checks cancel before every directory entry, see LoadReport::cancelled
+pub fn load_pack_cancellable(
+    path: &str,
+    name: &str,
+    desc: &str,
+    opts: &LoadOptions,
+    cancel: &AtomicBool,
+) -> (Pack, LoadReport) {
+    scan_pack(path, name, desc, opts, Some(cancel))
+}
+
+fn scan_pack(
+    path: &str,
+    name: &str,
+    desc: &str,
+    opts: &LoadOptions,
+    cancel: Option<&AtomicBool>,
+) -> (Pack, LoadReport) {
+    let start_path = path.to_string();
+    let mut count_loop = 0;
+    let mut count_oneshot = 0;
+    let mut report = LoadReport::default();
+    let mut pack = Pack {
+        samples: vec![],
+        meta: PackInfo {
+            description: desc.to_string(),
+            name: name.to_string(),
+            img: None,
+            num_samples: None,
+            source_query: None,
+        },
+    };
+
+    for entry in WalkDir::new(start_path)
+        .follow_links(opts.follow_links)
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if cancel.is_some_and(|cancel| cancel.load(AtomicOrdering::Relaxed)) {
+            debug!("Scan of {} cancelled", path);
+            report.cancelled = true;
+            break;
+        }
+        let entry_path = entry.path().display().to_string();
+        let entry_name = entry
+            .file_name()
+            .to_str()
+            .expect("Couldnt get entry name")
+            .to_string();
+
+        if is_sample_file_name(&entry_name) {
+            if is_blocked_path(&entry_path, opts) {
+                trace!("Skipping blocked file: {}", &entry_name);
+                report.skipped_blocked += 1;
+                continue;
+            }
+            if is_preview_file(&entry_name, opts) {
+                trace!("Skipping preview file: {}", &entry_name);
+                report.skipped_preview += 1;
+                continue;
+            }
+            if is_silent_file(&entry_path, opts) {
+                trace!("Skipping silent file: {}", &entry_name);
+                report.skipped_silent += 1;
+                continue;
+            }
+            let sample = sample_from_path(&entry_path, opts);
+            match sample.sampletype {
+                SampleType::OneShot => {
+                    count_oneshot += 1;
+                }
+                SampleType::Loop(_) => {
+                    count_loop += 1;
+                }
+            }
+            pack.samples.push(sample);
+            trace!("Sample found: {}", &entry_name);
+        }
+    }
+    debug!("Loops: {count_loop}, OneShots: {count_oneshot}");
+    refresh_count(&mut pack);
+    (pack, report)
+}
+
+pub fn save_lib_json(lib: &SampleLibrary, folder_path: &str) {
+    let json_lib = serde_json::to_string_pretty(&lib).expect("Couldnt create json data!");
+    let mut file =
+        File::create(folder_path.to_string() + &lib.name + ".json").expect("Couldnt open file!!");
+    file.write(json_lib.as_bytes())
+        .expect("Couldnt write file!!");
+}
+
+pub fn load_lib_json(path: &str) -> SampleLibrary {
+    let content = std::fs::read_to_string(path).expect("Couldn't read json file");
+    let lib: SampleLibrary = serde_json::from_str(&content).expect("Couldn't parse json");
+    lib
+}
+
+// Writes to a temporary sibling first so readers never see a half written file
+fn write_atomic(path: &str, data: &[u8]) -> Result<(), LibError> {
+    let tmp_path = path.to_string() + ".tmp";
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+// Pack names may hold separators or characters file systems reject
+fn file_slug(name: &str) -> String {
+    let slug: String = name
+        .chars()
+        .map(|c| {
+            if c.is_alphanumeric() || matches!(c, '-' | '_' | ' ') {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    if slug.trim().is_empty() {
+        "pack".to_string()
+    } else {
+        slug
+    }
+}
+
+fn write_pack_json(pack: &Pack, file_path: &str) -> Result<(), LibError> {
+    let json_pack = serde_json::to_string_pretty(pack)?;
+    write_atomic(file_path, json_pack.as_bytes())
+}
+
+// Saved as <pack name>.json with characters other than letters, digits, '-', '_'
+// and ' ' replaced by '_'
+pub fn save_pack_json(pack: &Pack, folder_path: &str) -> Result<(), LibError> {
+    write_pack_json(
+        pack,
+        &(folder_path.to_string() + &file_slug(&pack.meta.name) + ".json"),
+    )
+}
+
+pub fn load_pack_json(path: &str) -> Result<Pack, LibError> {
+    let content = std::fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+// A shard holds either a whole library or a single pack as written by save_lib_sharded
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Shard {
+    Library(SampleLibrary),
+    Pack(Box<Pack>),
+}
+
+fn load_shard_packs(path: &str) -> Result<Vec<Pack>, LibError> {
+    let reader = BufReader::new(File::open(path)?);
+    match serde_json::from_reader(reader)? {
+        Shard::Library(lib) => Ok(lib.packs),
+        Shard::Pack(pack) => Ok(vec![*pack]),
+    }
+}
+
+// Packs of every shard in one library. A pack name seen before is merged into the
+// first pack with that name, its samples added unless the path is already there.
+pub fn load_lib_sharded(paths: &[&str], name: &str) -> Result<SampleLibrary, LibError> {
+    let mut lib = SampleLibrary {
+        packs: vec![],
+        name: name.to_string(),
+    };
+    for path in paths {
+        let packs = load_shard_packs(path).map_err(|e| LibError::Shard {
+            path: path.to_string(),
+            source: Box::new(e),
+        })?;
+        for pack in packs {
+            match lib.packs.iter_mut().find(|p| p.meta.name == pack.meta.name) {
+                Some(existing) => {
+                    let known: HashSet<String> =
+                        existing.samples.iter().map(|s| s.path.clone()).collect();
+                    existing.samples.extend(
+                        pack.samples
+                            .into_iter()
+                            .filter(|s| !known.contains(&s.path)),
+                    );
+                    refresh_count(existing);
+                }
+                None => lib.packs.push(pack),
+            }
+        }
+    }
+    Ok(lib)
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct LibraryIndex {
+    pub name: String,
+    // Shard file names relative to the index file
+    pub shards: Vec<String>,
+}
+
+// One <position>_<pack name>.json per pack plus <library name>.index.json listing
+// them, all in folder_path which like in save_lib_json ends with a separator. The
+// position keeps packs sharing a name from overwriting each other.
+pub fn save_lib_sharded(lib: &SampleLibrary, folder_path: &str) -> Result<(), LibError> {
+    let mut index = LibraryIndex {
+        name: lib.name.clone(),
+        shards: vec![],
+    };
+    for (position, pack) in lib.packs.iter().enumerate() {
+        let shard = format!("{:03}_{}.json", position, file_slug(&pack.meta.name));
+        write_pack_json(pack, &(folder_path.to_string() + &shard))?;
+        index.shards.push(shard);
+    }
+    let json_index = serde_json::to_string_pretty(&index)?;
+    write_atomic(
+        &(folder_path.to_string() + &lib.name + ".index.json"),
+        json_index.as_bytes(),
+    )
+}
+
+pub fn load_lib_index(index_path: &str) -> Result<SampleLibrary, LibError> {
+    let content = std::fs::read_to_string(index_path)?;
+    let index: LibraryIndex = serde_json::from_str(&content)?;
+    let folder = std::path::Path::new(index_path)
+        .parent()
+        .unwrap_or(std::path::Path::new(""));
+    let paths: Vec<String> = index
+        .shards
+        .iter()
+        .map(|shard| folder.join(shard).display().to_string())
+        .collect();
+    let paths: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+    load_lib_sharded(&paths, &index.name)
+}
+
+// Heap entry ordered like search_lib's sort, ties broken by scan order
+struct RankedSample {
+    key: (RankKey, usize),
+    sample: Sample,
+}
+
+impl PartialEq for RankedSample {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl Eq for RankedSample {}
+
+impl PartialOrd for RankedSample {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankedSample {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+// Keeps only the best `limit` entries, the worst one sits on top of the heap
+fn push_bounded(heap: &mut BinaryHeap<RankedSample>, entry: RankedSample, limit: usize) {
+    heap.push(entry);
+    if heap.len() > limit {
+        heap.pop();
+    }
+}
+
+struct StreamSearch<'q> {
+    query: &'q SearchParams,
+    prepared: PreparedQuery,
+    limit: usize,
+    scanned: usize,
+    top: BinaryHeap<RankedSample>,
+}
+
+struct LibrarySeed<'s, 'q> {
+    search: &'s mut StreamSearch<'q>,
+}
+
+impl<'de> DeserializeSeed<'de> for LibrarySeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for LibrarySeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sample library")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "packs" {
+                map.next_value_seed(PacksSeed {
+                    search: &mut *self.search,
+                })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+struct PacksSeed<'s, 'q> {
+    search: &'s mut StreamSearch<'q>,
+}
+
+impl<'de> DeserializeSeed<'de> for PacksSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PacksSeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of packs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while seq
+            .next_element_seed(PackSeed {
+                search: &mut *self.search,
+            })?
+            .is_some()
+        {}
+        Ok(())
+    }
+}
+
+struct PackSeed<'s, 'q> {
+    search: &'s mut StreamSearch<'q>,
+}
+
+impl<'de> DeserializeSeed<'de> for PackSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for PackSeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a pack")
+    }
+
+    // meta is serialized after samples, so matches are held per pack until the name is known
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let mut pack_matches = BinaryHeap::new();
+        let mut pack_name = None;
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "samples" => map.next_value_seed(SamplesSeed {
+                    search: &mut *self.search,
+                    matches: &mut pack_matches,
+                })?,
+                "meta" => pack_name = Some(map.next_value::<PackInfo>()?.name),
+                _ => {
+                    map.next_value::<IgnoredAny>()?;
+                }
+            }
+        }
+        if let Some(pack_id) = &self.search.query.pack_id {
+            if pack_name.as_ref() != Some(pack_id) {
+                return Ok(());
+            }
+        }
+        let limit = self.search.limit;
+        let max_per_pack = self.search.query.max_per_pack.unwrap_or(usize::MAX);
+        for entry in pack_matches
+            .into_sorted_vec()
+            .into_iter()
+            .take(max_per_pack)
+        {
+            push_bounded(&mut self.search.top, entry, limit);
+        }
+        Ok(())
+    }
+}
+
+struct SamplesSeed<'s, 'q> {
+    search: &'s mut StreamSearch<'q>,
+    matches: &'s mut BinaryHeap<RankedSample>,
+}
+
+impl<'de> DeserializeSeed<'de> for SamplesSeed<'_, '_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for SamplesSeed<'_, '_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of samples")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(sample) = seq.next_element::<Sample>()? {
+            let search = &mut *self.search;
+            let explanation = evaluate_relevance(search.query, &sample, &search.prepared);
+            search.scanned += 1;
+            if explanation.score > 0.0 {
+                let key = (rank_key(&explanation), search.scanned);
+                push_bounded(self.matches, RankedSample { key, sample }, search.limit);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Same results as load_lib_json + search_lib while only holding max_results samples in memory.
+// random and tf_idf are not supported here, matches are always ranked by plain relevance
+// since word rarity isn't known until the whole file has been read.
+pub fn search_lib_file(path: &str, query: &SearchParams) -> Result<SearchResult, LibError> {
+    let offset = query.offset.unwrap_or(0);
+    let mut search = StreamSearch {
+        query,
+        prepared: prepare_query(query),
+        limit: result_limit(query).saturating_add(offset),
+        scanned: 0,
+        top: BinaryHeap::new(),
+    };
+    let reader = BufReader::new(File::open(path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    LibrarySeed {
+        search: &mut search,
+    }
+    .deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    let samples = search
+        .top
+        .into_sorted_vec()
+        .into_iter()
+        .skip(offset)
+        .map(|entry| entry.sample)
+        .collect();
+    Ok(SearchResult { samples })
+}
+
+// Unknown fields are skipped by the parser, so samples arrays are never materialized
+#[derive(Deserialize)]
+struct PackMetaOnly {
+    meta: PackInfo,
+}
+
+#[derive(Deserialize)]
+struct LibraryMetaOnly {
+    packs: Vec<PackMetaOnly>,
+}
+
+// Same as get_packs_metadata on a full load without parsing any samples
+pub fn load_packs_metadata_json(path: &str) -> Result<Vec<PackInfo>, LibError> {
+    let reader = BufReader::new(File::open(path)?);
+    let lib: LibraryMetaOnly = serde_json::from_reader(reader)?;
+    Ok(lib.packs.into_iter().map(|pack| pack.meta).collect())
+}
+
+// Parses only the pack at index from a library file, every other pack is skipped
+struct NthPackSeed {
+    index: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for NthPackSeed {
+    type Value = Option<Pack>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Option<Pack>, D::Error> {
+        deserializer.deserialize_map(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NthPackSeed {
+    type Value = Option<Pack>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sample library")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Option<Pack>, A::Error> {
+        let mut pack = None;
+        while let Some(key) = map.next_key::<String>()? {
+            if key == "packs" {
+                pack = map.next_value_seed(NthPackInListSeed { index: self.index })?;
+            } else {
+                map.next_value::<IgnoredAny>()?;
+            }
+        }
+        Ok(pack)
+    }
+}
+
+struct NthPackInListSeed {
+    index: usize,
+}
+
+impl<'de> DeserializeSeed<'de> for NthPackInListSeed {
+    type Value = Option<Pack>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Option<Pack>, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de> Visitor<'de> for NthPackInListSeed {
+    type Value = Option<Pack>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a list of packs")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Option<Pack>, A::Error> {
+        let mut pack = None;
+        let mut i = 0;
+        loop {
+            if i == self.index {
+                match seq.next_element::<Pack>()? {
+                    Some(found) => pack = Some(found),
+                    None => break,
+                }
+            } else if seq.next_element::<IgnoredAny>()?.is_none() {
+                break;
+            }
+            i += 1;
+        }
+        Ok(pack)
+    }
+}
+
+// meta is serialized after samples, so the pack is located by a metadata pass first
+pub fn load_pack_samples_json(path: &str, pack_name: &str) -> Result<Vec<Sample>, LibError> {
+    let index = load_packs_metadata_json(path)?
+        .iter()
+        .position(|meta| meta.name == pack_name)
+        .ok_or_else(|| LibError::PackNotFound(pack_name.to_string()))?;
+    let reader = BufReader::new(File::open(path)?);
+    let mut deserializer = serde_json::Deserializer::from_reader(reader);
+    let pack = NthPackSeed { index }.deserialize(&mut deserializer)?;
+    deserializer.end()?;
+    pack.map(|pack| pack.samples)
+        .ok_or_else(|| LibError::PackNotFound(pack_name.to_string()))
+}
+
+// Replaces the audiocloud_library, audiocloud_packs and audiocloud_samples tables in
+// db_path with the library's content, other tables of the database are left alone.
+// type is "loop" or "oneshot", tempo is NULL for one-shots and tags is a JSON array.
+#[cfg(feature = "sqlite")]
+pub fn export_sqlite(lib: &SampleLibrary, db_path: &str) -> Result<(), LibError> {
+    let mut conn = rusqlite::Connection::open(db_path)?;
+    let tx = conn.transaction()?;
+    tx.execute_batch(
+        "DROP TABLE IF EXISTS audiocloud_samples;
+         DROP TABLE IF EXISTS audiocloud_packs;
+         DROP TABLE IF EXISTS audiocloud_library;
+         CREATE TABLE audiocloud_library (name TEXT NOT NULL);
+         CREATE TABLE audiocloud_packs (
+             id INTEGER PRIMARY KEY,
+             name TEXT NOT NULL,
+             description TEXT NOT NULL,
+             img TEXT,
+             num_samples INTEGER
+         );
+         CREATE TABLE audiocloud_samples (
+             id INTEGER PRIMARY KEY,
+             pack_id INTEGER NOT NULL REFERENCES audiocloud_packs(id),
+             path TEXT NOT NULL,
+             name TEXT NOT NULL,
+             type TEXT NOT NULL,
+             tempo INTEGER,
+             bars INTEGER,
+             duration_secs REAL,
+             usage_count INTEGER,
+             tags TEXT NOT NULL
+         );",
+    )?;
+    tx.execute(
+        "INSERT INTO audiocloud_library (name) VALUES (?1)",
+        rusqlite::params![lib.name],
+    )?;
+    {
+        let mut insert_pack = tx.prepare(
+            "INSERT INTO audiocloud_packs (name, description, img, num_samples) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let mut insert_sample = tx.prepare(
+            "INSERT INTO audiocloud_samples (pack_id, path, name, type, tempo, bars, duration_secs, usage_count, tags)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+        )?;
+        for pack in &lib.packs {
+            let pack_id = insert_pack.insert(rusqlite::params![
+                pack.meta.name,
+                pack.meta.description,
+                pack.meta.img,
+                pack.meta.num_samples
+            ])?;
+            for sample in &pack.samples {
+                let (kind, tempo) = match sample.sampletype {
+                    SampleType::Loop(tempo) => ("loop", Some(tempo)),
+                    SampleType::OneShot => ("oneshot", None),
+                };
+                insert_sample.execute(rusqlite::params![
+                    pack_id,
+                    sample.path,
+                    sample.name,
+                    kind,
+                    tempo,
+                    sample.bars,
+                    sample.duration_secs.map(f64::from),
+                    sample.usage_count,
+                    serde_json::to_string(&sample.tags)?
+                ])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(())
+}
+
+#[cfg(feature = "sqlite")]
+fn sql_column<T: rusqlite::types::FromSql>(row: &rusqlite::Row, name: &str) -> Option<T> {
+    row.get::<_, Option<T>>(name).ok().flatten()
+}
+
+// Reads tables written by export_sqlite. Missing columns fall back to defaults,
+// a missing type is detected from the path and samples of unknown packs are skipped.
+#[cfg(feature = "sqlite")]
+pub fn import_sqlite(db_path: &str) -> Result<SampleLibrary, LibError> {
+    let conn = rusqlite::Connection::open(db_path)?;
+    let name = conn
+        .query_row(
+            "SELECT name FROM audiocloud_library",
+            rusqlite::params![],
+            |row| row.get::<_, String>(0),
+        )
+        .unwrap_or_else(|_| {
+            std::path::Path::new(db_path)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        });
+
+    let mut packs: Vec<Pack> = vec![];
+    let mut pack_indices: HashMap<i64, usize> = HashMap::new();
+    let mut pack_query = conn.prepare("SELECT * FROM audiocloud_packs ORDER BY id")?;
+    let pack_rows = pack_query.query_map(rusqlite::params![], |row| {
+        Ok((
+            row.get::<_, i64>("id")?,
+            PackInfo {
+                description: sql_column(row, "description").unwrap_or_default(),
+                name: sql_column(row, "name").unwrap_or_default(),
+                img: sql_column(row, "img"),
+                num_samples: sql_column(row, "num_samples"),
+                source_query: None,
+            },
+        ))
+    })?;
+    for pack_row in pack_rows {
+        let (id, meta) = pack_row?;
+        pack_indices.insert(id, packs.len());
+        packs.push(Pack {
+            samples: vec![],
+            meta,
+        });
+    }
+
+    let mut sample_query = conn.prepare("SELECT * FROM audiocloud_samples ORDER BY id")?;
+    let sample_rows = sample_query.query_map(rusqlite::params![], |row| {
+        let path: String = row.get("path")?;
+        let sampletype = match sql_column::<String>(row, "type").as_deref() {
+            Some("loop") => SampleType::Loop(sql_column(row, "tempo").unwrap_or(0)),
+            Some("oneshot") => SampleType::OneShot,
+            _ => detect_type(&path),
+        };
+        let tags = sql_column::<String>(row, "tags")
+            .and_then(|tags| serde_json::from_str(&tags).ok())
+            .unwrap_or_default();
+        Ok((
+            sql_column::<i64>(row, "pack_id"),
+            Sample {
+                name: sql_column(row, "name").unwrap_or_else(|| file_name_of(&path)),
+                sampletype,
+                bars: sql_column(row, "bars"),
+                modified: None,
+                duration_secs: sql_column::<f64>(row, "duration_secs").map(|d| d as f32),
+                usage_count: sql_column(row, "usage_count"),
+                tempo_range: detect_tempo_range(&path),
+                tags,
+                path,
+                effective_duration_secs: None,
+                type_overridden: false,
+                path_cache: PathCache::default(),
+            },
+        ))
+    })?;
+    for sample_row in sample_rows {
+        let (pack_id, sample) = sample_row?;
+        match pack_id.and_then(|id| pack_indices.get(&id)) {
+            Some(&index) => packs[index].samples.push(sample),
+            None => debug!("Skipping sample without a known pack: {}", sample.path),
+        }
+    }
+
+    Ok(SampleLibrary { packs, name })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn query(text: &str) -> SearchParams {
+        SearchParams {
+            query: text.to_string(),
+            ..Default::default()
+        }
+    }
+
+    // Paths don't have to exist, detection only looks at the names
+    fn pack_of(name: &str, paths: &[&str]) -> Pack {
+        pack_from_paths(paths, name, "", &LoadOptions::default())
+    }
+
+    fn lib_of(packs: Vec<Pack>) -> SampleLibrary {
+        SampleLibrary::new("test", packs)
+    }
+
+    fn paths_of(samples: &[Sample]) -> Vec<&str> {
+        samples.iter().map(|s| s.path.as_str()).collect()
+    }
+
+    // count samples spread over ten packs with a mix of drum, loop and FX names
+    fn generated_lib(count: usize) -> SampleLibrary {
+        let words = ["Kick", "Snare", "Hat", "Perc", "Bass", "Pad", "Riser"];
+        let packs = (0..10)
+            .map(|pack| {
+                let paths: Vec<String> = (pack..count)
+                    .step_by(10)
+                    .map(|i| match i % 3 {
+                        0 => format!(
+                            "/Lib/Pack {}/Loops/{}_loop_[{}].wav",
+                            pack,
+                            words[i % 7],
+                            80 + i % 80
+                        ),
+                        _ => format!("/Lib/Pack {}/One Shots/{}_{:04}.wav", pack, words[i % 7], i),
+                    })
+                    .collect();
+                let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+                pack_of(&format!("pack {}", pack), &paths)
+            })
+            .collect();
+        lib_of(packs)
+    }
+
+    // Fresh folder under the system temp dir, unique per test name
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("audiocloud_lib_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn touch(path: &std::path::Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, b"data").unwrap();
+    }
+
+    // Library saved as <folder>/test.json
+    fn saved_json_lib(dir: &std::path::Path, lib: &SampleLibrary) -> String {
+        save_lib_json(lib, &(dir.display().to_string() + "/"));
+        dir.join("test.json").display().to_string()
+    }
+
+    // 16-bit 44.1 kHz WAV, frames interleaved when channels > 1
+    #[cfg(feature = "audio")]
+    fn write_wav(path: &std::path::Path, channels: u16, frames: &[i16]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: 44100,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for frame in frames {
+            writer.write_sample(*frame).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    #[cfg(feature = "audio")]
+    fn sine(amplitude: f32, len: usize) -> Vec<i16> {
+        (0..len)
+            .map(|i| {
+                let phase = i as f32 * 440.0 * 2.0 * std::f32::consts::PI / 44100.0;
+                (phase.sin() * amplitude * i16::MAX as f32) as i16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn explain_relevance_reports_tempo_exclusion() {
+        let sample = get_sample("/lib/Loops/drum_loop_[100].wav");
+        let mut params = query("drum");
+        params.max_tempo = Some(90);
+        let explanation = explain_relevance(&params, &sample);
+        assert!(explanation.excluded_by_tempo);
+        assert_eq!(explanation.score, 0.0);
+        assert!(explanation.matched_tokens.is_empty());
+
+        params.max_tempo = Some(110);
+        let explanation = explain_relevance(&params, &sample);
+        assert!(!explanation.excluded_by_tempo);
+        assert_eq!(explanation.matched_tokens, vec!["drum"]);
+    }
+
+    #[test]
+    fn target_tempo_matches_within_tolerance_and_half_time() {
+        let lib = lib_of(vec![pack_of(
+            "loops",
+            &[
+                "/lib/Loops/drum_loop_[122].wav",
+                "/lib/Loops/drum_loop_[60].wav",
+                "/lib/Loops/drum_loop_[90].wav",
+            ],
+        )]);
+        let mut params = query("drum");
+        params.target_tempo = Some(120);
+        params.tempo_tolerance = Some(5);
+        let result = search_lib(&lib, &params);
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Loops/drum_loop_[122].wav"]
+        );
+
+        params.half_double_time = Some(true);
+        let result = search_lib(&lib, &params);
+        let mut found = paths_of(&result.samples);
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                "/lib/Loops/drum_loop_[122].wav",
+                "/lib/Loops/drum_loop_[60].wav"
+            ]
+        );
+    }
+
+    #[test]
+    fn half_double_time_does_not_overflow() {
+        let lib = lib_of(vec![pack_of("loops", &["/lib/Loops/drum_loop_[120].wav"])]);
+        let mut params = query("drum");
+        params.target_tempo = Some(u32::MAX);
+        params.half_double_time = Some(true);
+        assert!(search_lib(&lib, &params).samples.is_empty());
+    }
+
+    #[test]
+    fn detect_bars_reads_bar_counts() {
+        assert_eq!(detect_bars("/lib/Loops/drum_2bars_120.wav"), Some(2));
+        assert_eq!(detect_bars("/lib/Loops/drum 4 bars 120.wav"), Some(4));
+        assert_eq!(detect_bars("/lib/Loops/drum_16beats.wav"), Some(4));
+        assert_eq!(detect_bars("/lib/Loops/drum_loop_120.wav"), None);
+        assert_eq!(detect_bars("/lib/Loops/drum_4294967295bars.wav"), None);
+        assert_eq!(detect_bars("/lib/Loops 8 Bars/kick.wav"), None);
+        assert_eq!(detect_bars("/lib/Loops 8 Bars/drum_2bars.wav"), Some(2));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn follow_links_includes_symlinked_folders() {
+        let dir = temp_dir("follow_links");
+        touch(&dir.join("shared/kick.wav"));
+        touch(&dir.join("pack/snare.wav"));
+        std::os::unix::fs::symlink(dir.join("shared"), dir.join("pack/shared")).unwrap();
+        let root = dir.join("pack").display().to_string();
+
+        let mut opts = LoadOptions {
+            follow_links: true,
+            ..Default::default()
+        };
+        let pack = load_pack_with_options(&root, "pack", "", &opts);
+        assert_eq!(pack.samples.len(), 2);
+
+        opts.follow_links = false;
+        let pack = load_pack_with_options(&root, "pack", "", &opts);
+        assert_eq!(pack.samples.len(), 1);
+        assert!(pack.samples[0].path.ends_with("snare.wav"));
+    }
+
+    #[test]
+    fn pack_type_ratios_counts_loops() {
+        let lib = lib_of(vec![
+            pack_of(
+                "mixed",
+                &[
+                    "/lib/Mixed/drum_loop_[120].wav",
+                    "/lib/Mixed/bass_loop_[120].wav",
+                    "/lib/Mixed/synth_loop_[120].wav",
+                    "/lib/Mixed/kick.wav",
+                ],
+            ),
+            pack_of("empty", &[]),
+        ]);
+        assert_eq!(
+            pack_type_ratios(&lib),
+            vec![("mixed".to_string(), 0.75), ("empty".to_string(), 0.0)]
+        );
+    }
+
+    #[test]
+    fn format_sample_line_shows_tempo_and_type() {
+        let looped = get_sample("/lib/Loops/drum_loop_[120].wav");
+        assert_eq!(
+            format_sample_line(&looped),
+            format!(
+                "120 | Loop | {} | /lib/Loops/drum_loop_[120].wav",
+                looped.name
+            )
+        );
+        let shot = get_sample("/lib/Drums/kick.wav");
+        assert_eq!(
+            format_sample_line(&shot),
+            "- | OneShot | kick.wav | /lib/Drums/kick.wav"
+        );
+        let result = SearchResult {
+            samples: vec![looped, shot],
+        };
+        assert_eq!(format_result(&result).lines().count(), 2);
+    }
+
+    const SILENT_CHILD_ENV: &str = "AUDIOCLOUD_LIB_SILENT_CHILD";
+    const SILENT_BEGIN: &str = "--- search begin ---";
+    const SILENT_END: &str = "--- search end ---";
+
+    // Loads and searches between markers, run by search_does_not_print in a child
+    // process so everything the library writes to stdout or stderr can be read back
+    #[test]
+    fn search_output_child() {
+        if std::env::var_os(SILENT_CHILD_ENV).is_none() {
+            return;
+        }
+        let dir = temp_dir("search_output_child");
+        touch(&dir.join("Drums/kick.wav"));
+        touch(&dir.join("Loops/drum_loop_[120].wav"));
+        println!("{}", SILENT_BEGIN);
+        eprintln!("{}", SILENT_BEGIN);
+        let pack = load_pack(&dir.display().to_string(), "drums", "");
+        let lib = lib_of(vec![pack]);
+        let mut params = query("kick drum");
+        params.min_tempo = Some(130);
+        search_lib(&lib, &params);
+        use_sample_relevance(&params, &lib.packs[0].samples[0], &vec!["drum"]);
+        println!("{}", SILENT_END);
+        eprintln!("{}", SILENT_END);
+    }
+
+    // Diagnostics go through log, so a library search never writes to stdout
+    #[test]
+    fn search_does_not_print() {
+        let output = std::process::Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "tests::search_output_child", "--nocapture"])
+            .env(SILENT_CHILD_ENV, "1")
+            .output()
+            .unwrap();
+        assert!(output.status.success());
+        for stream in [&output.stdout, &output.stderr] {
+            let text = String::from_utf8_lossy(stream);
+            let begin = text.find(SILENT_BEGIN).unwrap() + SILENT_BEGIN.len();
+            let end = text.find(SILENT_END).unwrap();
+            assert_eq!(text[begin..end].trim(), "");
+        }
+    }
+
+    #[test]
+    fn tempos_match_any_listed_tempo() {
+        let lib = lib_of(vec![pack_of(
+            "loops",
+            &[
+                "/lib/Loops/drum_loop_[120].wav",
+                "/lib/Loops/drum_loop_[130].wav",
+                "/lib/Loops/drum_loop_[140].wav",
+            ],
+        )]);
+        let mut params = query("drum");
+        params.tempos = Some(vec![120, 140]);
+        let result = search_lib(&lib, &params);
+        let mut found = paths_of(&result.samples);
+        found.sort();
+        assert_eq!(
+            found,
+            vec![
+                "/lib/Loops/drum_loop_[120].wav",
+                "/lib/Loops/drum_loop_[140].wav"
+            ]
+        );
+    }
+
+    #[test]
+    fn autocomplete_suggests_words_by_prefix() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/Snare_01.wav",
+                "/lib/Drums/snare_02.wav",
+                "/lib/Drums/snap.wav",
+                "/lib/Drums/kick.wav",
+            ],
+        )]);
+        assert_eq!(autocomplete(&lib, "SNA", 1), vec!["snare"]);
+        assert_eq!(autocomplete(&lib, "sna", 5), vec!["snare", "snap"]);
+    }
+
+    #[test]
+    fn find_by_path_looks_up_samples() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"],
+        )]);
+        let sample = find_by_path(&lib, "/lib/Drums/snare.wav").unwrap();
+        assert_eq!(sample.name, "snare.wav");
+        assert!(find_by_path(&lib, "/lib/Drums/clap.wav").is_none());
+    }
+
+    #[test]
+    fn search_matches_folder_names() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Vintage Drums/kick.wav", "/lib/Modern/snare.wav"],
+        )]);
+        let result = search_lib(&lib, &query("vintage"));
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Vintage Drums/kick.wav"]
+        );
+        assert_eq!(result.samples[0].name, "kick.wav");
+    }
+
+    #[test]
+    fn file_slug_replaces_unsafe_characters() {
+        assert_eq!(file_slug("Drum Kit_01-B"), "Drum Kit_01-B");
+        assert_eq!(file_slug("../evil/pack"), "___evil_pack");
+        assert_eq!(file_slug("C:\\pack"), "C__pack");
+        assert_eq!(file_slug(""), "pack");
+    }
+
+    #[test]
+    fn save_pack_json_stays_in_folder() {
+        let dir = temp_dir("save_pack_json");
+        let folder = dir.display().to_string() + "/";
+        let mut pack = pack_of("drums", &["/lib/Drums/kick.wav"]);
+        pack.meta.name = "../drums".to_string();
+        save_pack_json(&pack, &folder).unwrap();
+        let saved = dir.join("___drums.json");
+        assert!(saved.exists());
+        let loaded = load_pack_json(&saved.display().to_string()).unwrap();
+        assert_eq!(loaded.meta.name, "../drums");
+        assert_eq!(loaded.samples.len(), 1);
+    }
+
+    #[test]
+    fn import_pack_adds_and_rejects_duplicates() {
+        let mut lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        let summary = import_pack(&mut lib, pack_of("keys", &["/lib/Keys/piano.wav"])).unwrap();
+        assert_eq!(summary.added, 1);
+        assert_eq!(lib.packs.len(), 3);
+
+        let duplicate = pack_of("drums", &["/lib/Other/snare.wav"]);
+        assert!(matches!(
+            import_pack(&mut lib, duplicate),
+            Err(LibError::PackExists(name)) if name == "drums"
+        ));
+        assert_eq!(lib.packs.len(), 3);
+    }
+
+    #[test]
+    fn pack_dominant_format_picks_most_common_extension() {
+        let pack = pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.WAV",
+                "/lib/Drums/hat.wav",
+                "/lib/Drums/clap.mp3",
+            ],
+        );
+        assert_eq!(pack_dominant_format(&pack), Some("wav".to_string()));
+        assert_eq!(pack_dominant_format(&pack_of("empty", &[])), None);
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn skip_silent_drops_silent_wavs() {
+        let dir = temp_dir("skip_silent");
+        write_wav(&dir.join("silent.wav"), 1, &[0; 4410]);
+        write_wav(&dir.join("tone.wav"), 1, &sine(0.5, 4410));
+        fs::write(dir.join("empty.wav"), b"").unwrap();
+        let opts = LoadOptions {
+            skip_silent: true,
+            silence_threshold: 0.01,
+            ..Default::default()
+        };
+        let pack = load_pack_with_options(&dir.display().to_string(), "pack", "", &opts);
+        assert_eq!(pack.samples.len(), 1);
+        assert_eq!(pack.samples[0].name, "tone.wav");
+    }
+
+    #[test]
+    fn search_lib_file_matches_json_search() {
+        let dir = temp_dir("search_lib_file_json");
+        let lib = lib_of(vec![
+            pack_of(
+                "drums",
+                &["/lib/Drums/kick.wav", "/lib/Drums/kick_loop_[120].wav"],
+            ),
+            pack_of("bass", &["/lib/Bass/kick_bass.wav", "/lib/Bass/sub.wav"]),
+        ]);
+        let path = saved_json_lib(&dir, &lib);
+        let params = query("kick");
+        let streamed = search_lib_file(&path, &params).unwrap();
+        let loaded = search_lib(&load_lib_json(&path), &params);
+        assert_eq!(paths_of(&streamed.samples), paths_of(&loaded.samples));
+        assert_eq!(streamed.samples.len(), 3);
+    }
+
+    #[test]
+    fn diff_libraries_buckets_changes() {
+        let old = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/perc.wav",
+            ],
+        )]);
+        let mut new = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/perc.wav",
+                "/lib/Drums/clap.wav",
+            ],
+        )]);
+        new.packs[0].samples[1].sampletype = SampleType::Loop(120);
+
+        let diff = diff_libraries(&old, &new);
+        let paths = |entries: &[DiffEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.sample.path.clone()).collect()
+        };
+        assert_eq!(paths(&diff.added), vec!["/lib/Drums/clap.wav"]);
+        assert_eq!(paths(&diff.removed), vec!["/lib/Drums/snare.wav"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].old.sampletype, SampleType::OneShot);
+        assert_eq!(diff.changed[0].new.sampletype, SampleType::Loop(120));
+    }
+
+    #[test]
+    fn apply_diff_reproduces_new_library() {
+        let mut old = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"],
+        )]);
+        let mut new = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/clap.wav"]),
+            pack_of("loops", &["/lib/Loops/drum_loop_[120].wav"]),
+        ]);
+        new.packs[0].samples[0].sampletype = SampleType::Loop(90);
+
+        let diff = diff_libraries(&old, &new);
+        apply_diff(&mut old, &diff);
+        let contents = |lib: &SampleLibrary| {
+            let mut contents: Vec<(String, String, SampleType)> = lib
+                .packs
+                .iter()
+                .flat_map(|pack| {
+                    pack.samples
+                        .iter()
+                        .map(|s| (pack.meta.name.clone(), s.path.clone(), s.sampletype.clone()))
+                })
+                .collect();
+            contents.sort_by(|a, b| (&a.0, &a.1).cmp(&(&b.0, &b.1)));
+            contents
+        };
+        assert_eq!(contents(&old), contents(&new));
+        assert!(old.packs.iter().all(|pack| !counts_are_stale(pack)));
+    }
+
+    #[test]
+    fn normalize_tempo_folds_into_band() {
+        assert_eq!(normalize_tempo(70, 90, 180), 140);
+        assert_eq!(normalize_tempo(280, 90, 180), 140);
+        assert_eq!(normalize_tempo(120, 90, 180), 120);
+        assert_eq!(normalize_tempo(0, 90, 180), 0);
+        assert_eq!(normalize_tempo(120, u32::MAX, u32::MAX), 120);
+        // An inverted band can't be reached either
+        assert_eq!(normalize_tempo(120, 180, 90), 120);
+    }
+
+    #[test]
+    fn search_paged_counts_all_matches() {
+        let paths: Vec<String> = (0..15)
+            .map(|i| format!("/lib/Drums/kick_{:02}.wav", i))
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        let lib = lib_of(vec![pack_of("drums", &paths)]);
+        let mut params = query("kick");
+        params.max_results = Some(4);
+        params.offset = Some(12);
+        let paged = search_paged(&lib, &params);
+        assert_eq!(paged.total_matches, 15);
+        assert_eq!(paged.offset, 12);
+        assert_eq!(paged.samples.len(), 3);
+    }
+
+    #[test]
+    fn added_since_returns_newer_samples() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/hat.wav",
+            ],
+        )]);
+        let base = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000);
+        lib.packs[0].samples[0].modified = Some(base);
+        lib.packs[0].samples[1].modified = Some(base + std::time::Duration::from_secs(60));
+        let cutoff = base + std::time::Duration::from_secs(30);
+        let newer: Vec<&str> = added_since(&lib, cutoff)
+            .iter()
+            .map(|s| s.path.as_str())
+            .collect();
+        assert_eq!(newer, vec!["/lib/Drums/snare.wav"]);
+    }
+
+    #[test]
+    fn group_construction_kits_by_kit_folder() {
+        let pack = pack_of(
+            "kits",
+            &[
+                "/lib/Construction Kits/Kit A/bass.wav",
+                "/lib/Construction Kits/Kit B/drums.wav",
+                "/lib/Construction Kits/Kit A/keys.wav",
+                "/lib/One Shots/kick.wav",
+            ],
+        );
+        let kits = group_construction_kits(&pack);
+        let kits: Vec<(&str, Vec<&str>)> = kits
+            .iter()
+            .map(|(folder, samples)| (folder.as_str(), paths_of(samples)))
+            .collect();
+        assert_eq!(
+            kits,
+            vec![
+                (
+                    "/lib/Construction Kits/Kit A",
+                    vec![
+                        "/lib/Construction Kits/Kit A/bass.wav",
+                        "/lib/Construction Kits/Kit A/keys.wav"
+                    ]
+                ),
+                (
+                    "/lib/Construction Kits/Kit B",
+                    vec!["/lib/Construction Kits/Kit B/drums.wav"]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn sample_type_round_trips_through_strings() {
+        for text in ["loop:120", "oneshot"] {
+            let parsed: SampleType = text.parse().unwrap();
+            assert_eq!(parsed.to_string(), text);
+        }
+        assert_eq!("Loop:90".parse::<SampleType>(), Ok(SampleType::Loop(90)));
+        for bad in ["loop", "loop:fast", "drum", ""] {
+            assert_eq!(
+                bad.parse::<SampleType>(),
+                Err(ParseSampleTypeError(bad.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn blocked_folders_are_skipped() {
+        let dir = temp_dir("blocked_folders");
+        touch(&dir.join("Drums/kick.wav"));
+        touch(&dir.join("Drums/Rejected/snare.wav"));
+        let opts = LoadOptions {
+            blocked_folders: vec!["rejected".to_string()],
+            ..Default::default()
+        };
+        let pack = load_pack_with_options(&dir.display().to_string(), "pack", "", &opts);
+        assert_eq!(pack.samples.len(), 1);
+        assert_eq!(pack.samples[0].name, "kick.wav");
+    }
+
+    #[test]
+    fn loop_bars_from_tempo_and_duration() {
+        assert_eq!(loop_bars(120, 8.0, 4), Some(4.0));
+        assert_eq!(loop_bars(0, 8.0, 4), None);
+        let mut sample = get_sample("/lib/Loops/drum_loop_[120].wav");
+        sample.duration_secs = Some(8.0);
+        assert_eq!(sample_loop_bars(&sample, 4), Some(4.0));
+    }
+
+    #[test]
+    fn samples_are_totally_ordered() {
+        let mut cached = get_sample("/lib/Drums/kick.wav");
+        let plain = cached.clone();
+        cached.refresh_path_cache();
+        assert_eq!(cached, plain);
+
+        let mut timed = plain.clone();
+        timed.duration_secs = Some(1.0);
+        let mut nan = plain.clone();
+        nan.duration_secs = Some(f32::NAN);
+        assert_eq!(nan, nan.clone());
+        let mut sorted = vec![nan.clone(), timed.clone(), plain.clone()];
+        sorted.sort();
+        assert_eq!(sorted, vec![plain, timed, nan]);
+    }
+
+    #[test]
+    fn boolean_query_combines_terms() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/kick_loop_[120].wav",
+                "/lib/Drums/hat.wav",
+            ],
+        )]);
+        let mut params = query("(kick OR snare) AND NOT loop");
+        params.boolean_query = Some(true);
+        let result = search_lib(&lib, &params);
+        let mut found = paths_of(&result.samples);
+        found.sort();
+        assert_eq!(found, vec!["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]);
+
+        assert!(parse_query_expr("(kick OR").is_err());
+        assert!(parse_query_expr("kick snare)").is_err());
+        params.query = "(kick OR".to_string();
+        assert!(search_lib(&lib, &params).samples.is_empty());
+    }
+
+    #[test]
+    fn flat_access_covers_every_pack() {
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+            pack_of("empty", &[]),
+        ]);
+        let per_pack: usize = lib.packs.iter().map(|pack| pack.samples.len()).sum();
+        assert_eq!(all_samples(&lib).count(), per_pack);
+        assert_eq!(sample_count(&lib), 3);
+    }
+
+    #[test]
+    fn refresh_names_uses_file_names() {
+        let mut lib = lib_of(vec![pack_of("drums", &["/lib/Drums/kick.wav"])]);
+        lib.packs[0].samples[0].name = "/lib/Drums/kick.wav".to_string();
+        refresh_names(&mut lib);
+        assert_eq!(lib.packs[0].samples[0].name, "kick.wav");
+    }
+
+    #[test]
+    fn usage_weight_boosts_used_samples() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick_a.wav", "/lib/Drums/kick_b.wav"],
+        )]);
+        lib.packs[0].samples[1].increment_usage();
+        let mut params = query("kick");
+        params.usage_weight = Some(0.5);
+        let result = search_lib(&lib, &params);
+        assert_eq!(result.samples[0].name, "kick_b.wav");
+        let boosted = explain_relevance(&params, &result.samples[0]).score;
+        let plain = explain_relevance(&params, &result.samples[1]).score;
+        assert_eq!(boosted - plain, 0.5);
+    }
+
+    #[test]
+    fn audit_pack_reports_missing_metadata() {
+        let mut pack = pack_of("drums", &["/lib/Drums/kick.wav"]);
+        pack.meta.description = "  ".to_string();
+        assert_eq!(
+            audit_pack(&pack),
+            vec![PackIssue::MissingDescription, PackIssue::MissingArtwork]
+        );
+        pack.meta.description = "Dry drums".to_string();
+        pack.meta.img = Some("cover.png".to_string());
+        assert!(audit_pack(&pack).is_empty());
+    }
+
+    #[test]
+    fn recompute_counts_fixes_stale_counts() {
+        let mut lib = lib_of(vec![pack_of("drums", &["/lib/Drums/kick.wav"])]);
+        assert!(!counts_are_stale(&lib.packs[0]));
+        let snare = get_sample("/lib/Drums/snare.wav");
+        lib.packs[0].samples.push(snare);
+        assert!(counts_are_stale(&lib.packs[0]));
+        recompute_counts(&mut lib);
+        assert!(!counts_are_stale(&lib.packs[0]));
+        assert_eq!(lib.packs[0].meta.num_samples, Some(2));
+    }
+
+    #[test]
+    fn search_partitioned_puts_exact_matches_first() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/snre_fx.wav",
+                "/lib/Drums/kick.wav",
+            ],
+        )]);
+        let mut params = query("snre");
+        params.fuzzy = Some(true);
+        let result = search_partitioned(&lib, &params);
+        assert_eq!(paths_of(&result.exact), vec!["/lib/Drums/snre_fx.wav"]);
+        assert_eq!(paths_of(&result.fuzzy), vec!["/lib/Drums/snare.wav"]);
+        let result = search_lib(&lib, &params);
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Drums/snre_fx.wav", "/lib/Drums/snare.wav"]
+        );
+    }
+
+    #[test]
+    fn common_root_finds_shared_folder() {
+        let lib = lib_of(vec![
+            pack_of("drums", &["/Users/me/Samples/Drums/kick.wav"]),
+            pack_of("bass", &["/Users/me/Samples/Bass/sub.wav"]),
+        ]);
+        assert_eq!(common_root(&lib), Some("/Users/me/Samples/".to_string()));
+
+        let lib = lib_of(vec![pack_of(
+            "mixed",
+            &["C:\\Samples\\kick.wav", "D:\\Samples\\snare.wav"],
+        )]);
+        assert_eq!(common_root(&lib), None);
+        assert_eq!(common_root(&lib_of(vec![])), None);
+    }
+
+    #[test]
+    fn folder_type_wins_over_keywords() {
+        let opts = LoadOptions {
+            oneshot_folders: vec!["one shots".to_string()],
+            loop_folders: vec!["loops".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(
+            detect_type_with_options("/lib/One Shots/kick_loop_demo.wav", &opts),
+            SampleType::OneShot
+        );
+        // The folder closest to the file decides
+        assert_eq!(
+            detect_type_with_options("/lib/Loops/One Shots/hit_loop_[120].wav", &opts),
+            SampleType::OneShot
+        );
+        assert_eq!(
+            detect_type_with_options("/lib/Loops/drum_loop_[120].wav", &opts),
+            SampleType::Loop(120)
+        );
+    }
+
+    #[test]
+    fn tempo_ranges_match_overlapping_windows() {
+        assert_eq!(
+            detect_tempo_range("/lib/Loops/groove_120-124.wav"),
+            Some((120, 124))
+        );
+        assert_eq!(detect_tempo_range("/lib/Vol 100-200/kick_01-02.wav"), None);
+        // A range alone isn't a loop signal
+        assert_eq!(
+            get_sample("/lib/Drums/hit_120-124.wav").sampletype,
+            SampleType::OneShot
+        );
+
+        let lib = lib_of(vec![pack_of(
+            "loops",
+            &["/lib/Loops/drum_loop_120-124.wav"],
+        )]);
+        assert_eq!(lib.packs[0].samples[0].sampletype, SampleType::Loop(120));
+        let mut params = query("drum");
+        params.target_tempo = Some(122);
+        params.tempo_tolerance = Some(1);
+        assert_eq!(search_lib(&lib, &params).samples.len(), 1);
+        params.target_tempo = Some(130);
+        assert!(search_lib(&lib, &params).samples.is_empty());
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn export_sqlite_writes_one_row_per_sample() {
+        let dir = temp_dir("export_sqlite");
+        let db_path = dir.join("lib.db").display().to_string();
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("loops", &["/lib/Loops/drum_loop_[120].wav"]),
+        ]);
+        let conn = rusqlite::Connection::open(&db_path).unwrap();
+        // A table of the host app that happens to share a generic name
+        conn.execute_batch("CREATE TABLE samples (id INTEGER); INSERT INTO samples VALUES (1);")
+            .unwrap();
+        export_sqlite(&lib, &db_path).unwrap();
+        let count = |table: &str| -> i64 {
+            conn.query_row(
+                &format!("SELECT COUNT(*) FROM {}", table),
+                rusqlite::params![],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(count("audiocloud_packs"), 2);
+        assert_eq!(count("audiocloud_samples"), 3);
+        assert_eq!(count("samples"), 1);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn sqlite_round_trips_library() {
+        let dir = temp_dir("sqlite_round_trip");
+        let db_path = dir.join("lib.db").display().to_string();
+        let mut lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("loops", &["/lib/Loops/drum_loop_[120].wav"]),
+        ]);
+        lib.packs[0].samples[0].tags = vec!["punchy".to_string()];
+        lib.packs[0].samples[1].increment_usage();
+        export_sqlite(&lib, &db_path).unwrap();
+        let imported = import_sqlite(&db_path).unwrap();
+        assert_eq!(imported.name, lib.name);
+        assert_eq!(imported.packs.len(), 2);
+        for (pack, original) in imported.packs.iter().zip(&lib.packs) {
+            assert_eq!(pack.meta.name, original.meta.name);
+            assert_eq!(pack.samples, original.samples);
+        }
+    }
+
+    #[test]
+    fn occurrence_decay_counts_repeats() {
+        let sample = get_sample("/lib/Kick/kick_kick.wav");
+        let params = query("kick");
+        assert_eq!(explain_relevance(&params, &sample).score, 1.0);
+
+        let mut decayed = query("kick");
+        decayed.occurrence_decay = Some(0.5);
+        // Three occurrences: 1 + 0.5 + 0.25
+        assert_eq!(explain_relevance(&decayed, &sample).score, 1.75);
+    }
+
+    #[test]
+    fn queries_split_on_commas() {
+        assert_eq!(split_query("kick, snare"), split_query("kick snare"));
+        assert_eq!(
+            split_query("kick;\tsnare,,hat"),
+            vec!["kick", "snare", "hat"]
+        );
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/hat.wav",
+            ],
+        )]);
+        let comma = search_lib(&lib, &query("kick, snare"));
+        let space = search_lib(&lib, &query("kick snare"));
+        assert_eq!(paths_of(&comma.samples), paths_of(&space.samples));
+        assert_eq!(comma.samples.len(), 2);
+    }
+
+    #[test]
+    fn unmatched_terms_reports_missing_words() {
+        let lib = lib_of(vec![pack_of("drums", &["/lib/Drums/808_kick.wav"])]);
+        assert_eq!(
+            unmatched_terms(&lib, &query("808 kick 808nonexistent")),
+            vec!["808nonexistent"]
+        );
+        assert!(unmatched_terms(&lib, &query("808 kick")).is_empty());
+    }
+
+    #[test]
+    fn load_packs_metadata_json_matches_full_load() {
+        let dir = temp_dir("packs_metadata_json");
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        let path = saved_json_lib(&dir, &lib);
+        let streamed = load_packs_metadata_json(&path).unwrap();
+        let loaded = get_packs_metadata(&load_lib_json(&path));
+        let summary = |metas: &[PackInfo]| -> Vec<(String, Option<u32>)> {
+            metas
+                .iter()
+                .map(|meta| (meta.name.clone(), meta.num_samples))
+                .collect()
+        };
+        assert_eq!(summary(&streamed), summary(&loaded));
+        assert_eq!(streamed.len(), 2);
+    }
+
+    #[test]
+    fn load_pack_samples_json_reads_one_pack() {
+        let dir = temp_dir("pack_samples_json");
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        let path = saved_json_lib(&dir, &lib);
+        let samples = load_pack_samples_json(&path, "bass").unwrap();
+        assert_eq!(samples, lib.packs[1].samples);
+        assert!(matches!(
+            load_pack_samples_json(&path, "keys"),
+            Err(LibError::PackNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn sample_types_filter_any_listed_type() {
+        let lib = lib_of(vec![pack_of(
+            "mixed",
+            &["/lib/Mixed/drum_loop_[120].wav", "/lib/Mixed/drum_hit.wav"],
+        )]);
+        let mut params = query("drum");
+        params.sample_types = Some(vec![SampleType::Loop(0), SampleType::OneShot]);
+        assert_eq!(search_lib(&lib, &params).samples.len(), 2);
+
+        // Any loop matches whatever its tempo
+        params.sample_types = Some(vec![SampleType::Loop(90)]);
+        let result = search_lib(&lib, &params);
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Mixed/drum_loop_[120].wav"]
+        );
+    }
+
+    #[test]
+    fn group_versions_clusters_numbered_takes() {
+        let pack = pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick_01.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/kick_02.wav",
+            ],
+        );
+        let groups = group_versions(&pack);
+        let groups: Vec<Vec<&str>> = groups.iter().map(|group| paths_of(group)).collect();
+        assert_eq!(
+            groups,
+            vec![
+                vec!["/lib/Drums/kick_01.wav", "/lib/Drums/kick_02.wav"],
+                vec!["/lib/Drums/snare.wav"]
+            ]
+        );
+    }
+
+    #[test]
+    fn random_seed_gives_stable_order() {
+        let paths: Vec<String> = (0..20)
+            .map(|i| format!("/lib/Drums/kick_{:02}.wav", i))
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        let lib = lib_of(vec![pack_of("drums", &paths)]);
+        let mut params = query("kick");
+        params.max_results = Some(20);
+        params.random = Some(true);
+        params.random_seed = Some(7);
+        let first = search_lib(&lib, &params);
+        let second = search_lib(&lib, &params);
+        assert_eq!(paths_of(&first.samples), paths_of(&second.samples));
+        assert_eq!(first.samples.len(), 20);
+
+        params.random_seed = Some(8);
+        let other = search_lib(&lib, &params);
+        assert_ne!(paths_of(&first.samples), paths_of(&other.samples));
+    }
+
+    #[test]
+    fn sample_id_is_stable_per_path() {
+        let kick = get_sample("/lib/Drums/kick.wav");
+        assert_eq!(
+            sample_id(&kick),
+            sample_id(&get_sample("/lib/Drums/kick.wav"))
+        );
+        assert_eq!(
+            sample_id(&kick),
+            sample_id(&get_sample("\\lib\\Drums\\kick.wav"))
+        );
+        assert_ne!(
+            sample_id(&kick),
+            sample_id(&get_sample("/lib/Drums/snare.wav"))
+        );
+    }
+
+    #[test]
+    fn max_per_pack_lets_small_packs_through() {
+        let big: Vec<String> = (0..20)
+            .map(|i| format!("/lib/A/kick_kick_{:02}.wav", i))
+            .collect();
+        let big: Vec<&str> = big.iter().map(|p| p.as_str()).collect();
+        let lib = lib_of(vec![
+            pack_of("a", &big),
+            pack_of("b", &["/lib/B/kick_01.wav", "/lib/B/kick_02.wav"]),
+        ]);
+        let mut params = query("kick");
+        params.max_per_pack = Some(3);
+        let result = search_lib(&lib, &params);
+        assert_eq!(result.samples.len(), 5);
+        let from_b = result
+            .samples
+            .iter()
+            .filter(|s| s.path.starts_with("/lib/B/"))
+            .count();
+        assert_eq!(from_b, 2);
+        assert_eq!(count_matches(&lib, &params), 5);
+    }
+
+    #[test]
+    fn type_overrides_change_only_listed_samples() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/fill.wav",
+                "/lib/Drums/kick.wav",
+                "/lib/Loops/drum_loop_[120].wav",
+            ],
+        )]);
+        let overrides = HashMap::from([("/lib/Drums/fill.wav".to_string(), SampleType::Loop(100))]);
+        apply_type_overrides(&mut lib, &overrides);
+        let types: Vec<SampleType> = lib.packs[0]
+            .samples
+            .iter()
+            .map(|s| s.sampletype.clone())
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                SampleType::Loop(100),
+                SampleType::OneShot,
+                SampleType::Loop(120)
+            ]
+        );
+    }
+
+    #[test]
+    fn estimate_pack_scan_counts_files_and_bytes() {
+        let dir = temp_dir("estimate_scan");
+        fs::create_dir_all(dir.join("Drums")).unwrap();
+        fs::write(dir.join("Drums/kick.wav"), [0u8; 100]).unwrap();
+        fs::write(dir.join("Drums/snare.mp3"), [0u8; 50]).unwrap();
+        fs::write(dir.join("Drums/notes.txt"), [0u8; 1000]).unwrap();
+        let estimate = estimate_pack_scan(&dir.display().to_string(), &LoadOptions::default());
+        assert_eq!(estimate.files, 2);
+        assert_eq!(estimate.total_bytes, 150);
+    }
+
+    #[test]
+    fn cancelled_scan_stops_early() {
+        let dir = temp_dir("cancel_scan");
+        touch(&dir.join("kick.wav"));
+        touch(&dir.join("snare.wav"));
+        let root = dir.display().to_string();
+        let opts = LoadOptions::default();
+
+        let cancel = AtomicBool::new(true);
+        let (pack, report) = load_pack_cancellable(&root, "pack", "", &opts, &cancel);
+        assert!(report.cancelled);
+        assert!(pack.samples.is_empty());
+
+        cancel.store(false, AtomicOrdering::Relaxed);
+        let (pack, report) = load_pack_cancellable(&root, "pack", "", &opts, &cancel);
+        assert!(!report.cancelled);
+        assert_eq!(pack.samples.len(), 2);
+    }
+
+    #[test]
+    fn resolve_tempo_prefers_brackets_and_ignores_folder_numbers() {
+        let guess = resolve_tempo("/lib/Loops/remix_128_of_the_90s_track_[120].wav");
+        assert_eq!(guess.tempo, Some(120));
+        assert_eq!(guess.source, Some(TempoSource::Bracket));
+        assert_eq!(guess.alternates, vec![128, 90]);
+
+        let guess = resolve_tempo("/lib/Vol 100/140 BPM/groove_loop.wav");
+        assert_eq!(guess.tempo, None);
+        let guess = resolve_tempo("/lib/Vol 100/groove_loop_95bpm.wav");
+        assert_eq!(guess.tempo, Some(95));
+        assert_eq!(guess.source, Some(TempoSource::Bpm));
+    }
+
+    #[test]
+    fn search_multi_interleaves_by_relevance() {
+        let mut first = lib_of(vec![pack_of(
+            "drums",
+            &["/one/kick.wav", "/one/kick_snare.wav"],
+        )]);
+        first.name = "one".to_string();
+        let mut second = lib_of(vec![pack_of(
+            "drums",
+            &["/two/snare.wav", "/two/kick_snare.wav"],
+        )]);
+        second.name = "two".to_string();
+        let result = search_multi(&[&first, &second], &query("kick snare"));
+        assert_eq!(
+            paths_of(&result.samples),
+            vec![
+                "/one/kick_snare.wav",
+                "/two/kick_snare.wav",
+                "/one/kick.wav",
+                "/two/snare.wav"
+            ]
+        );
+        let sourced = search_multi_sourced(&[&first, &second], &query("kick snare"));
+        let libraries: Vec<&str> = sourced.iter().map(|s| s.library.as_str()).collect();
+        assert_eq!(libraries, vec!["one", "two", "one", "two"]);
+    }
+
+    #[test]
+    fn search_response_serializes_to_json() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/kick_2.wav"],
+        )]);
+        let mut params = query("Kick");
+        params.max_results = Some(1);
+        let response = search_response(&lib, &params);
+        assert_eq!(response.total_matches, 2);
+        assert_eq!(response.query, "kick");
+        let json: serde_json::Value = serde_json::to_value(&response).unwrap();
+        let field = |name: &str| json.get(name).cloned().unwrap_or_default();
+        assert_eq!(field("total_matches").as_u64(), Some(2));
+        assert_eq!(field("max_results").as_u64(), Some(1));
+        assert_eq!(
+            field("samples").as_array().map(|samples| samples.len()),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn preview_patterns_match_whole_words() {
+        let opts = LoadOptions::default();
+        assert!(is_preview_file("Song_Demo.wav", &opts));
+        assert!(is_preview_file("pack previews.mp3", &opts));
+        assert!(!is_preview_file("demolition_kick.wav", &opts));
+        assert!(!is_preview_file("nopreview.wav", &opts));
+
+        let opts = LoadOptions {
+            preview_patterns: Some(vec!["Mix".to_string()]),
+            ..Default::default()
+        };
+        assert!(is_preview_file("track_mix.wav", &opts));
+        assert!(!is_preview_file("song_demo.wav", &opts));
+        let opts = LoadOptions {
+            preview_patterns: Some(vec![]),
+            ..Default::default()
+        };
+        assert!(!is_preview_file("song_demo.wav", &opts));
+    }
+
+    #[test]
+    fn pack_similarity_is_jaccard_of_name_words() {
+        let a = pack_of("a", &["/a/kick_snare.wav", "/a/hat_clap.wav"]);
+        let b = pack_of("b", &["/b/kick_snare.wav", "/b/tom_ride.wav"]);
+        // Shared {kick, snare} out of {kick, snare, hat, clap, tom, ride}
+        assert!((pack_similarity(&a, &b) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(pack_similarity(&a, &a), 1.0);
+        assert_eq!(pack_similarity(&pack_of("x", &[]), &pack_of("y", &[])), 0.0);
+    }
+
+    #[test]
+    fn by_tempo_proximity_orders_by_distance() {
+        let lib = lib_of(vec![pack_of(
+            "loops",
+            &[
+                "/lib/Loops/drum_loop_[125].wav",
+                "/lib/Loops/drum_loop_[118].wav",
+                "/lib/Loops/drum_loop_[120].wav",
+                "/lib/Drums/kick.wav",
+            ],
+        )]);
+        let tempos: Vec<Option<u32>> = by_tempo_proximity(&lib, 121)
+            .iter()
+            .map(|s| s.sampletype.tempo())
+            .collect();
+        assert_eq!(tempos, vec![Some(120), Some(118), Some(125)]);
+    }
+
+    #[test]
+    fn pack_tempo_range_spans_loop_tempos() {
+        let pack = pack_of(
+            "loops",
+            &[
+                "/lib/Loops/drum_loop_[120].wav",
+                "/lib/Loops/drum_loop_[95].wav",
+                "/lib/Loops/drum_loop_[140].wav",
+                "/lib/Drums/kick.wav",
+            ],
+        );
+        assert_eq!(pack_tempo_range(&pack), Some((95, 140)));
+        let shots = pack_of("shots", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]);
+        assert_eq!(pack_tempo_range(&shots), None);
+    }
+
+    #[test]
+    fn search_union_keeps_best_ranking_once() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/kick_snare.wav",
+            ],
+        )]);
+        let result = search_union(&lib, &[query("kick"), query("kick snare")]);
+        let found = paths_of(&result.samples);
+        assert_eq!(found.len(), 3);
+        // Two tokens matched in the second query rank it first
+        assert_eq!(found[0], "/lib/Drums/kick_snare.wav");
+    }
+
+    #[test]
+    fn search_intersection_needs_every_query() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/kick_snare.wav",
+            ],
+        )]);
+        let result = search_intersection(&lib, &[query("kick"), query("snare")]);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/Drums/kick_snare.wav"]);
+        assert!(search_intersection(&lib, &[]).samples.is_empty());
+    }
+
+    #[test]
+    fn count_matches_counts_every_match() {
+        let paths: Vec<String> = (0..25)
+            .map(|i| format!("/lib/Drums/kick_{:02}.wav", i))
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        let lib = lib_of(vec![
+            pack_of("drums", &paths),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        let params = query("kick");
+        assert_eq!(count_matches(&lib, &params), 25);
+        assert_eq!(
+            count_matches(&lib, &params),
+            search_paged(&lib, &params).total_matches
+        );
+    }
+
+    #[test]
+    fn validate_params_accepts_defaults() {
+        assert_eq!(validate_params(&query("kick")), Ok(()));
+    }
+
+    #[test]
+    fn validate_params_rejects_inverted_tempo_range() {
+        let mut params = query("kick");
+        params.min_tempo = Some(140);
+        params.max_tempo = Some(120);
+        assert_eq!(
+            validate_params(&params),
+            Err(ParamError::InvertedTempoRange { min: 140, max: 120 })
+        );
+    }
+
+    #[test]
+    fn validate_params_rejects_non_positive_max_results() {
+        let mut params = query("kick");
+        params.max_results = Some(0);
+        assert_eq!(
+            validate_params(&params),
+            Err(ParamError::InvalidMaxResults(0))
+        );
+    }
+
+    #[test]
+    fn validate_params_rejects_empty_filters() {
+        let mut params = query("kick");
+        params.pack_id = Some(String::new());
+        assert_eq!(
+            validate_params(&params),
+            Err(ParamError::EmptyField("pack_id"))
+        );
+
+        let mut params = query("kick");
+        params.tempos = Some(vec![]);
+        assert_eq!(
+            validate_params(&params),
+            Err(ParamError::EmptyField("tempos"))
+        );
+
+        let mut params = query("kick");
+        params.sample_types = Some(vec![]);
+        assert_eq!(
+            validate_params(&params),
+            Err(ParamError::EmptyField("sample_types"))
+        );
+    }
+
+    #[test]
+    fn validate_params_rejects_malformed_boolean_query() {
+        let mut params = query("kick AND (snare");
+        params.boolean_query = Some(true);
+        assert!(matches!(
+            validate_params(&params),
+            Err(ParamError::MalformedQuery(_))
+        ));
+    }
+
+    #[test]
+    fn negative_max_results_uses_default_limit() {
+        let paths: Vec<String> = (0..15)
+            .map(|i| format!("/lib/Drums/kick_{:02}.wav", i))
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        let lib = lib_of(vec![pack_of("drums", &paths)]);
+        let mut params = query("kick");
+        params.max_results = Some(-1);
+        assert_eq!(search_lib(&lib, &params).samples.len(), 10);
+    }
+
+    #[test]
+    fn load_lib_sharded_json_merges_packs() {
+        let dir = temp_dir("lib_sharded_json");
+        let folder = dir.display().to_string() + "/";
+        let mut first = lib_of(vec![pack_of("drums", &["/lib/Drums/kick.wav"])]);
+        first.name = "first".to_string();
+        let mut second = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        second.name = "second".to_string();
+        save_lib_json(&first, &folder);
+        save_lib_json(&second, &folder);
+        let first_path = folder.clone() + "first.json";
+        let second_path = folder + "second.json";
+        let lib = load_lib_sharded(&[&first_path, &second_path], "merged").unwrap();
+        assert_eq!(lib.packs.len(), 2);
+        assert_eq!(lib.packs[0].samples.len(), 2);
+        assert_eq!(sample_count(&lib), 3);
+    }
+
+    #[test]
+    fn save_lib_sharded_json_round_trips() {
+        let dir = temp_dir("lib_sharded_round_trip_json");
+        let folder = dir.display().to_string() + "/";
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+            pack_of("drums", &["/lib/More Drums/hat.wav"]),
+        ]);
+        save_lib_sharded(&lib, &folder).unwrap();
+        assert!(dir.join("000_drums.json").exists());
+        assert!(dir.join("002_drums.json").exists());
+
+        let loaded = load_lib_index(&(folder + "test.index.json")).unwrap();
+        assert_eq!(loaded.name, lib.name);
+        // Packs sharing a name are merged on load, no samples are lost
+        assert_eq!(loaded.packs.len(), 2);
+        assert_eq!(sample_count(&loaded), 4);
+        let mut drums = loaded.packs[0].samples.clone();
+        drums.sort();
+        let mut expected: Vec<Sample> = lib.packs[0].samples.clone();
+        expected.extend(lib.packs[2].samples.clone());
+        expected.sort();
+        assert_eq!(drums, expected);
+        assert_eq!(loaded.packs[1].samples, lib.packs[1].samples);
+    }
+
+    #[test]
+    fn locate_sample_normalizes_paths() {
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav"]),
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        let (pack, sample) = locate_sample(&lib, "/lib/Bass/../Drums//kick.wav").unwrap();
+        assert_eq!(pack, "drums");
+        assert_eq!(sample.path, "/lib/Drums/kick.wav");
+        let (pack, _) = locate_sample(&lib, "Bass/sub.wav").unwrap();
+        assert_eq!(pack, "bass");
+        assert!(locate_sample(&lib, "/other/Drums/kick.wav").is_none());
+    }
+
+    #[test]
+    fn token_frequencies_ranks_common_words() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick_hard.wav",
+                "/lib/Drums/kick_soft.wav",
+                "/lib/Drums/kick_01.wav",
+                "/lib/Drums/snare_hard.wav",
+                "/lib/Drums/snare_soft.wav",
+                "/lib/Drums/snare_tight.wav",
+                "/lib/Drums/hat.wav",
+            ],
+        )]);
+        let top: Vec<String> = token_frequencies(&lib, 2)
+            .into_iter()
+            .map(|(token, _)| token)
+            .collect();
+        assert_eq!(top, vec!["kick", "snare"]);
+    }
+
+    #[test]
+    fn sample_type_tempo() {
+        assert_eq!(SampleType::Loop(120).tempo(), Some(120));
+        assert_eq!(SampleType::Loop(0).tempo(), None);
+        assert_eq!(SampleType::OneShot.tempo(), None);
+    }
+
+    #[cfg(feature = "decentsampler")]
+    #[test]
+    fn decentsampler_preset_has_one_sample_per_result() {
+        let result = SearchResult {
+            samples: vec![
+                get_sample("/lib/Drums/kick.wav"),
+                get_sample("/lib/Drums/snare & clap.wav"),
+            ],
+        };
+        let xml = result_to_decentsampler(&result);
+        assert_eq!(xml.matches("<sample ").count(), 2);
+        assert!(xml.contains("path=\"/lib/Drums/kick.wav\""));
+        assert!(xml.contains("path=\"/lib/Drums/snare &amp; clap.wav\""));
+        assert!(xml.contains("rootNote=\"37\""));
+    }
+
+    #[test]
+    fn counted_library_tracks_changes() {
+        let mut counted = CountedLibrary::new(lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"],
+        )]));
+        assert_eq!(counted.total_samples(), 2);
+
+        let lib = counted.library_mut();
+        lib.packs[0].samples.pop();
+        import_pack(
+            lib,
+            pack_of("bass", &["/lib/Bass/sub.wav", "/lib/Bass/808.wav"]),
+        )
+        .unwrap();
+        lib.packs[1].samples.push(get_sample("/lib/Bass/reese.wav"));
+        assert_eq!(counted.total_samples(), 4);
+        assert_eq!(counted.total_samples(), sample_count(counted.library()));
+        assert_eq!(counted.into_inner().packs.len(), 2);
+    }
+
+    #[test]
+    fn drum_category_from_keywords() {
+        let category = |path: &str| drum_category(&get_sample(path));
+        assert_eq!(
+            category("/lib/Drums/808_kick.wav"),
+            Some(DrumCategory::Kick)
+        );
+        assert_eq!(
+            category("/lib/Drums/closed_hat.wav"),
+            Some(DrumCategory::Hat)
+        );
+        assert_eq!(category("/lib/Synths/pad.wav"), None);
+        // The file name wins over the folder
+        assert_eq!(
+            category("/lib/Kicks/snare_01.wav"),
+            Some(DrumCategory::Snare)
+        );
+    }
+
+    #[test]
+    fn drum_category_search_uses_custom_keywords() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/thump.wav"],
+        )]);
+        let mut params = query("drums");
+        params.drum_category = Some(DrumCategory::Kick);
+        let result = search_lib(&lib, &params);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/Drums/kick.wav"]);
+
+        params.drum_keywords = Some(vec![(DrumCategory::Kick, vec!["thump".to_string()])]);
+        let result = search_lib(&lib, &params);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/Drums/thump.wav"]);
+    }
+
+    #[test]
+    fn enharmonic_and_relative_keys() {
+        let key = |name: &str| name.parse::<MusicalKey>().unwrap();
+        assert!(key("C#").is_enharmonic(&key("Db")));
+        assert!(!key("C#").is_enharmonic(&key("C#m")));
+        assert!(key("Am").is_relative(&key("C")));
+        assert!(key("C").is_relative(&key("Am")));
+        assert_eq!(key("Db").to_string(), "C#");
+        assert_eq!(MusicalKey::new(13, true), key("C#m"));
+        assert_eq!(key("Bb").pitch_class(), 10);
+        assert!(key("Bbm").is_minor());
+        assert!("H".parse::<MusicalKey>().is_err());
+    }
+
+    #[test]
+    fn musical_key_json_rejects_bad_pitch_class() {
+        let parsed: MusicalKey = serde_json::from_str(r#"{"pitch_class":9,"minor":true}"#).unwrap();
+        assert_eq!(parsed, MusicalKey::new(9, true));
+        assert!(serde_json::from_str::<MusicalKey>(r#"{"pitch_class":12,"minor":false}"#).is_err());
+    }
+
+    #[test]
+    fn camelot_codes_and_compatible_keys() {
+        let a_minor: MusicalKey = "Am".parse().unwrap();
+        assert_eq!(a_minor.camelot().as_deref(), Some("8A"));
+        assert_eq!(
+            "C".parse::<MusicalKey>().unwrap().camelot().as_deref(),
+            Some("8B")
+        );
+        let mut codes: Vec<String> = a_minor
+            .compatible_keys()
+            .iter()
+            .filter_map(|key| key.camelot())
+            .collect();
+        codes.sort();
+        assert_eq!(codes, vec!["7A", "8A", "8B", "9A"]);
+    }
+
+    #[test]
+    fn classification_warnings_flag_loop_named_one_shots() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/perc_loop.wav"],
+        )]);
+        lib.packs[0].samples[1].sampletype = SampleType::OneShot;
+        let warnings = classification_warnings(&lib);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0.path, "/lib/Drums/perc_loop.wav");
+        assert_eq!(warnings[0].1, "one-shot named like a loop");
+    }
+
+    #[test]
+    fn pack_from_paths_keeps_given_files_in_order() {
+        let pack = pack_from_paths(
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Loops/drum_loop_[120].wav",
+                "/lib/Drums/notes.txt",
+                "/lib/FX/riser.mp3",
+            ],
+            "picked",
+            "Hand-picked",
+            &LoadOptions::default(),
+        );
+        let types: Vec<SampleType> = pack.samples.iter().map(|s| s.sampletype.clone()).collect();
+        assert_eq!(
+            types,
+            vec![
+                SampleType::OneShot,
+                SampleType::Loop(120),
+                SampleType::OneShot
+            ]
+        );
+        assert_eq!(pack.meta.num_samples, Some(3));
+        assert_eq!(pack.meta.description, "Hand-picked");
+    }
+
+    #[test]
+    fn smart_pack_holds_every_match() {
+        let paths: Vec<String> = (0..12)
+            .map(|i| format!("/lib/Drums/kick_{:02}.wav", i))
+            .collect();
+        let mut paths: Vec<&str> = paths.iter().map(|p| p.as_str()).collect();
+        paths.extend(["/lib/Drums/snare.wav", "/lib/Drums/hat.wav"]);
+        let lib = lib_of(vec![pack_of("drums", &paths)]);
+        let pack = smart_pack(&lib, &query("kick"), "Kicks");
+        assert_eq!(pack.samples.len(), 12);
+        assert!(pack.samples.iter().all(|s| s.name.starts_with("kick")));
+        assert_eq!(pack.meta.num_samples, Some(12));
+        assert_eq!(
+            pack.meta.source_query.as_ref().map(|q| q.query.as_str()),
+            Some("kick")
+        );
+    }
+
+    #[test]
+    fn path_cache_does_not_change_results() {
+        let mut lib = generated_lib(500);
+        let queries = ["kick", "SNARE loop", "pack 3 hat", "riser -loop", "bass"];
+        let uncached: Vec<Vec<String>> = queries
+            .iter()
+            .map(|text| {
+                let result = search_lib(&lib, &query(text));
+                result.samples.into_iter().map(|s| s.path).collect()
+            })
+            .collect();
+        build_path_cache(&mut lib);
+        for (text, expected) in queries.iter().zip(uncached) {
+            let result = search_lib(&lib, &query(text));
+            let paths: Vec<String> = result.samples.into_iter().map(|s| s.path).collect();
+            assert_eq!(paths, expected, "query {:?}", text);
+        }
+    }
+
+    // Timing only, run with cargo test --release -- --ignored --nocapture
+    #[test]
+    #[ignore]
+    fn path_cache_benchmark() {
+        let mut lib = generated_lib(100_000);
+        let params = query("kick loop");
+        let time_queries = |lib: &SampleLibrary| {
+            let start = std::time::Instant::now();
+            for _ in 0..20 {
+                search_lib(lib, &params);
+            }
+            start.elapsed() / 20
+        };
+        let uncached = time_queries(&lib);
+        build_path_cache(&mut lib);
+        let cached = time_queries(&lib);
+        eprintln!("per query: {:?} uncached, {:?} cached", uncached, cached);
+    }
+
+    #[test]
+    fn kit_tempo_single_or_multi() {
+        let kit = pack_of(
+            "kit",
+            &[
+                "/lib/Kit/bass_loop_[120].wav",
+                "/lib/Kit/drum_loop_[120].wav",
+                "/lib/Kit/kick.wav",
+            ],
+        );
+        assert_eq!(kit_tempo(&kit.samples), KitTempo::Single(120));
+        let mixed = pack_of(
+            "kit",
+            &[
+                "/lib/Kit/bass_loop_[128].wav",
+                "/lib/Kit/drum_loop_[120].wav",
+            ],
+        );
+        assert_eq!(kit_tempo(&mixed.samples), KitTempo::Multi(vec![120, 128]));
+        let shots = pack_of("kit", &["/lib/Kit/kick.wav"]);
+        assert_eq!(kit_tempo(&shots.samples), KitTempo::Unknown);
+    }
+
+    #[test]
+    fn daily_picks_depend_only_on_seed() {
+        let lib = generated_lib(200);
+        let picks = |seed: u64| -> Vec<String> {
+            daily_picks(&lib, seed, 5)
+                .iter()
+                .map(|s| s.path.clone())
+                .collect()
+        };
+        assert_eq!(picks(20261015), picks(20261015));
+        assert_eq!(picks(20261015).len(), 5);
+        assert_ne!(picks(20261015), picks(20261016));
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn measure_loudness_of_a_sine() {
+        let dir = temp_dir("measure_loudness");
+        let path = dir.join("tone.wav");
+        write_wav(&path, 1, &sine(0.5, 44100));
+        let info = measure_loudness(&path.display().to_string()).unwrap();
+        // RMS of a sine is its amplitude / sqrt(2)
+        assert!((info.rms - 0.5 / 2f32.sqrt()).abs() < 0.01);
+        assert!((info.peak - 0.5).abs() < 0.01);
+        assert!((info.peak_dbfs - -6.02).abs() < 0.1);
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn effective_duration_trims_silence() {
+        let dir = temp_dir("effective_duration");
+        let path = dir.join("late_hit.wav");
+        let mut frames = vec![0i16; 22050];
+        frames.extend(sine(0.5, 44100));
+        frames.extend(vec![0i16; 11025]);
+        write_wav(&path, 1, &frames);
+        let path = path.display().to_string();
+        let trimmed = effective_duration(&path, -40.0).unwrap();
+        assert!((trimmed - 1.0).abs() < 0.01, "trimmed to {}", trimmed);
+
+        let opts = LoadOptions {
+            trim_threshold_db: Some(-40.0),
+            ..Default::default()
+        };
+        let sample = sample_from_path(&path, &opts);
+        assert_eq!(sample.effective_duration_secs, Some(trimmed));
+    }
+
+    #[test]
+    fn apply_tag_file_json_tags_known_samples() {
+        let dir = temp_dir("tag_file_json");
+        let tags_path = dir.join("tags.json");
+        fs::write(
+            &tags_path,
+            r#"{
+                "/lib/Drums/kick.wav": ["punchy", "dry"],
+                "/lib/Drums/snare.wav": ["dry"],
+                "/lib/Drums/missing.wav": ["lost"]
+            }"#,
+        )
+        .unwrap();
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick.wav",
+                "/lib/Drums/snare.wav",
+                "/lib/Drums/hat.wav",
+            ],
+        )]);
+        lib.packs[0].samples[0].tags = vec!["dry".to_string()];
+        let report = apply_tag_file(&mut lib, &tags_path.display().to_string()).unwrap();
+        assert_eq!(
+            report,
+            TagApplyReport {
+                tagged: 2,
+                unknown_paths: 1
+            }
+        );
+        let samples = &lib.packs[0].samples;
+        assert_eq!(samples[0].tags, vec!["dry", "punchy"]);
+        assert_eq!(samples[1].tags, vec!["dry"]);
+        assert!(samples[2].tags.is_empty());
+    }
+
+    #[test]
+    fn packs_sort_by_name_regardless_of_insertion_order() {
+        let names = |packs: Vec<&Pack>| -> Vec<String> {
+            packs.iter().map(|pack| pack.meta.name.clone()).collect()
+        };
+        let forward = lib_of(vec![
+            pack_of("Bass", &[]),
+            pack_of("drums", &[]),
+            pack_of("Vocals", &[]),
+        ]);
+        let mut backward = lib_of(vec![
+            pack_of("Vocals", &[]),
+            pack_of("drums", &[]),
+            pack_of("Bass", &[]),
+        ]);
+        let expected = vec!["Bass", "drums", "Vocals"];
+        assert_eq!(names(sorted_packs(&forward)), expected);
+        assert_eq!(names(sorted_packs(&backward)), expected);
+        sort_packs(&mut backward);
+        assert_eq!(names(backward.packs.iter().collect()), expected);
+    }
+
+    #[test]
+    fn vocal_filter_uses_query_keywords() {
+        let vocal = pack_of("vocals", &["/lib/female_vocal_120.wav"]);
+        let kick = pack_of("drums", &["/lib/kick.wav"]);
+        assert!(is_vocal(&vocal.samples[0]));
+        assert!(!is_vocal(&kick.samples[0]));
+
+        let lib = lib_of(vec![vocal, kick]);
+        let mut params = query("lib");
+        params.vocal = Some(true);
+        let result = search_lib(&lib, &params);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/female_vocal_120.wav"]);
+        params.vocal = Some(false);
+        let result = search_lib(&lib, &params);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/kick.wav"]);
+        params.vocal_keywords = Some(vec!["kick".to_string()]);
+        let result = search_lib(&lib, &params);
+        assert_eq!(paths_of(&result.samples), vec!["/lib/female_vocal_120.wav"]);
+    }
+
+    #[test]
+    fn reclassify_only_changes_affected_samples() {
+        let paths = [
+            "/lib/Grooves/groove_120.wav",
+            "/lib/Grooves/shaker.wav",
+            "/lib/Hits/kick.wav",
+            "/lib/Loops/drum_loop_[128].wav",
+        ];
+        let mut lib = lib_of(vec![pack_of("pack", &paths)]);
+        let types = |lib: &SampleLibrary| -> Vec<SampleType> {
+            lib.packs[0]
+                .samples
+                .iter()
+                .map(|s| s.sampletype.clone())
+                .collect()
+        };
+        let before = types(&lib);
+        let opts = LoadOptions {
+            loop_folders: vec!["grooves".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(reclassify_if_options_changed(&mut lib, &opts), 2);
+        let after = types(&lib);
+        assert_eq!(after[0], SampleType::Loop(120));
+        assert_eq!(after[1], SampleType::Loop(0));
+        assert_eq!(after[2..], before[2..]);
+        // Nothing left to change with the same options
+        assert_eq!(reclassify_if_options_changed(&mut lib, &opts), 0);
+    }
+
+    #[test]
+    fn search_by_tempo_buckets_loops() {
+        let lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/lib/Drums/drum_loop_[120].wav",
+                "/lib/Drums/drum_fill_loop_[120].wav",
+                "/lib/Drums/drum_loop_[128].wav",
+                "/lib/Drums/drum_hit.wav",
+            ],
+        )]);
+        let params = query("drum");
+        let groups = search_by_tempo(&lib, &params, false);
+        assert_eq!(groups.keys().copied().collect::<Vec<_>>(), vec![120, 128]);
+        let mut at_120 = paths_of(&groups[&120]);
+        at_120.sort();
+        assert_eq!(
+            at_120,
+            vec![
+                "/lib/Drums/drum_fill_loop_[120].wav",
+                "/lib/Drums/drum_loop_[120].wav"
+            ]
+        );
+        assert_eq!(
+            paths_of(&groups[&128]),
+            vec!["/lib/Drums/drum_loop_[128].wav"]
+        );
+        let with_untimed = search_by_tempo(&lib, &params, true);
+        assert_eq!(paths_of(&with_untimed[&0]), vec!["/lib/Drums/drum_hit.wav"]);
+    }
+
+    #[test]
+    fn integrity_reports_duplicate_names_and_stale_counts() {
+        let mut stale = pack_of("drums", &["/lib/Drums/kick.wav"]);
+        stale.meta.num_samples = Some(5);
+        let lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/snare.wav"]),
+            stale,
+            pack_of("bass", &["/lib/Bass/sub.wav"]),
+        ]);
+        assert_eq!(
+            validate_library_integrity(&lib),
+            vec![
+                IntegrityIssue::DuplicatePackName("drums".to_string()),
+                IntegrityIssue::StaleSampleCount {
+                    pack: "drums".to_string()
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rebase_paths_swaps_only_whole_folder_prefixes() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &[
+                "/old/Samples/kick.wav",
+                "/older/Samples/snare.wav",
+                "/elsewhere/hat.wav",
+            ],
+        )]);
+        assert_eq!(rebase_paths(&mut lib, "/old/", "/new"), 1);
+        assert_eq!(
+            paths_of(&lib.packs[0].samples),
+            vec![
+                "/new/Samples/kick.wav",
+                "/older/Samples/snare.wav",
+                "/elsewhere/hat.wav"
+            ]
+        );
+    }
+
+    #[test]
+    fn ndjson_has_one_parsable_line_per_sample() {
+        let lib = generated_lib(30);
+        let result = search_lib(&lib, &query("loop"));
+        assert!(!result.samples.is_empty());
+        let text = result_to_ndjson(&result);
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), result.samples.len());
+        for (line, sample) in lines.iter().zip(&result.samples) {
+            let parsed: Sample = serde_json::from_str(line).unwrap();
+            assert_eq!(parsed.path, sample.path);
+        }
+        let mut written = vec![];
+        write_result_ndjson(&result, &mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), text);
+    }
+
+    #[test]
+    fn boolean_query_repeated_terms_score_once() {
+        let pack = pack_of("drums", &["/lib/Drums/kick.wav"]);
+        let sample = &pack.samples[0];
+        let score = |text: &str| {
+            let mut params = query(text);
+            params.boolean_query = Some(true);
+            explain_relevance(&params, sample).score
+        };
+        assert!(score("kick") > 0.0);
+        assert_eq!(score("kick kick"), score("kick"));
+        assert_eq!(score("kick OR kick"), score("kick"));
+        let expr = parse_query_expr("kick snare kick -hat").unwrap();
+        assert_eq!(expr.positive_terms(), vec!["kick", "snare"]);
+    }
+
+    #[cfg(feature = "audio")]
+    #[test]
+    fn dual_mono_wav_is_flagged() {
+        let dir = temp_dir("dual_mono");
+        let tone = sine(0.5, 4410);
+        let dual: Vec<i16> = tone.iter().flat_map(|v| [*v, *v]).collect();
+        let inverted: Vec<i16> = tone.iter().flat_map(|v| [*v, -*v]).collect();
+        let dual_path = dir.join("dual.wav");
+        let inverted_path = dir.join("inverted.wav");
+        let mono_path = dir.join("mono.wav");
+        write_wav(&dual_path, 2, &dual);
+        write_wav(&inverted_path, 2, &inverted);
+        write_wav(&mono_path, 1, &tone);
+
+        let info = stereo_analysis(&dual_path.display().to_string()).unwrap();
+        assert!(info.dual_mono);
+        assert!((info.correlation.unwrap() - 1.0).abs() < 1e-4);
+        let info = stereo_analysis(&inverted_path.display().to_string()).unwrap();
+        assert!(!info.dual_mono);
+        assert!((info.correlation.unwrap() + 1.0).abs() < 1e-4);
+        let info = stereo_analysis(&mono_path.display().to_string()).unwrap();
+        assert_eq!(
+            (info.channels, info.dual_mono, info.correlation),
+            (1, false, None)
+        );
+
+        let paths: Vec<String> = [&dual_path, &inverted_path, &mono_path]
+            .iter()
+            .map(|path| path.display().to_string())
+            .collect();
+        let paths: Vec<&str> = paths.iter().map(|path| path.as_str()).collect();
+        let lib = lib_of(vec![pack_of("pack", &paths)]);
+        let flagged: Vec<&str> = dual_mono_samples(&lib)
+            .iter()
+            .map(|sample| sample.path.as_str())
+            .collect();
+        assert_eq!(flagged, vec![paths[0]]);
+    }
+
+    #[test]
+    fn name_template_fills_placeholders() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Loops/drum_loop_[120].wav", "/lib/Hits/kick.wav"],
+        )]);
+        apply_name_template(&mut lib, "{tempo}_{name}").unwrap();
+        let names: Vec<&str> = lib.packs[0]
+            .samples
+            .iter()
+            .map(|s| s.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["120_drum_loop_[120].wav", "_kick.wav"]);
+        // Applying again starts from the file name, not the previous result
+        apply_name_template(&mut lib, "{pack}-{type}-{name}").unwrap();
+        assert_eq!(lib.packs[0].samples[1].name, "drums-oneshot-kick.wav");
+
+        let error = apply_name_template(&mut lib, "{tempo}_{bpm}").unwrap_err();
+        assert_eq!(
+            error,
+            TemplateError("unknown placeholder {bpm}".to_string())
+        );
+        assert!(parse_name_template("{name").is_err());
+        assert_eq!(lib.packs[0].samples[1].name, "drums-oneshot-kick.wav");
+    }
+
+    #[test]
+    fn halftime_pairs_match_names_without_loop_keywords() {
+        let lib = lib_of(vec![pack_of(
+            "grooves",
+            &[
+                "/lib/Grooves/groove_120.wav",
+                "/lib/Grooves/groove_240.wav",
+                "/lib/Grooves/pad_loop_[90].wav",
+                "/lib/Grooves/bass_loop_[180].wav",
+            ],
+        )]);
+        let pairs = halftime_pairs(&lib);
+        let pairs: Vec<(&str, &str)> = pairs
+            .iter()
+            .map(|(slow, fast)| (slow.path.as_str(), fast.path.as_str()))
+            .collect();
+        assert_eq!(
+            pairs,
+            vec![("/lib/Grooves/groove_120.wav", "/lib/Grooves/groove_240.wav")]
+        );
+    }
+
+    #[test]
+    fn autocomplete_in_pack_only_suggests_that_pack() {
+        let drums = pack_of(
+            "drums",
+            &["/lib/Snares/snare_tight.wav", "/lib/Snares/snare_wide.wav"],
+        );
+        let fx = pack_of("fx", &["/lib/Sweeps/sweep_up.wav"]);
+        let lib = lib_of(vec![drums, fx]);
+        assert!(autocomplete(&lib, "sw", 10).contains(&"sweep".to_string()));
+        let drums = &lib.packs[0];
+        assert!(autocomplete_in_pack(drums, "sw", 10).is_empty());
+        assert_eq!(autocomplete_in_pack(drums, "sn", 10), vec!["snare"]);
+    }
+
+    #[test]
+    fn mp3_bytes_in_wav_file_are_flagged() {
+        assert_eq!(
+            sniff_format(b"ID3\x04\x00\x00\x00\x00\x00\x00"),
+            Some("mp3")
+        );
+        assert_eq!(sniff_format(&[0xFF, 0xFB, 0x90, 0x64]), Some("mp3"));
+        assert_eq!(sniff_format(b"RIFF\x24\x00\x00\x00WAVEfmt "), Some("wav"));
+        assert_eq!(sniff_format(b"data"), None);
+
+        let dir = temp_dir("extension_mismatch");
+        let fake = dir.join("kick.wav");
+        let real = dir.join("snare.wav");
+        fs::write(&fake, [0xFF, 0xFB, 0x90, 0x64, 0, 0, 0, 0, 0, 0, 0, 0]).unwrap();
+        fs::write(&real, b"RIFF\x24\x00\x00\x00WAVEfmt ").unwrap();
+        let paths = [fake.display().to_string(), real.display().to_string()];
+        let lib = lib_of(vec![pack_of("drums", &[&paths[0], &paths[1]])]);
+        let mismatches = extension_mismatches(&lib);
+        let mismatches: Vec<(&str, &str)> = mismatches
+            .iter()
+            .map(|(sample, format)| (sample.path.as_str(), format.as_str()))
+            .collect();
+        assert_eq!(mismatches, vec![(paths[0].as_str(), "mp3")]);
+    }
+
+    #[test]
+    fn field_weights_rank_name_matches_above_folder_matches() {
+        let lib = lib_of(vec![pack_of(
+            "pack",
+            &["/lib/Kicks/thump.wav", "/lib/Hits/kick_hard.wav"],
+        )]);
+        let mut params = query("kick");
+        params.field_weights = Some(FieldWeights {
+            name: 3.0,
+            folder: 1.0,
+            ..Default::default()
+        });
+        let by_name = explain_relevance(&params, &lib.packs[0].samples[1]).score;
+        let by_folder = explain_relevance(&params, &lib.packs[0].samples[0]).score;
+        assert!(by_folder > 0.0);
+        assert!(by_name > by_folder);
+        let result = search_lib(&lib, &params);
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Hits/kick_hard.wav", "/lib/Kicks/thump.wav"]
+        );
+
+        params.field_weights = Some(FieldWeights {
+            name: 1.0,
+            folder: 3.0,
+            ..Default::default()
+        });
+        let result = search_lib(&lib, &params);
+        assert_eq!(
+            paths_of(&result.samples),
+            vec!["/lib/Kicks/thump.wav", "/lib/Hits/kick_hard.wav"]
+        );
+    }
+
+    #[test]
+    fn add_file_places_samples_and_skips_filtered_files() {
+        let mut lib = lib_of(vec![
+            pack_of("drums", &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]),
+            pack_of(
+                "loops",
+                &["/lib/Loops/a_loop_[90].wav", "/lib/Loops/b_loop_[100].wav"],
+            ),
+        ]);
+        let opts = LoadOptions::default();
+        let added = add_file(&mut lib, "/lib/Loops/c_loop_[120].wav", &opts)
+            .unwrap()
+            .unwrap();
+        assert_eq!(added.sampletype, SampleType::Loop(120));
+        assert_eq!(lib.packs[1].samples.len(), 3);
+        assert_eq!(lib.packs[1].meta.num_samples, Some(3));
+        assert_eq!(lib.packs[1].samples[2].path, "/lib/Loops/c_loop_[120].wav");
+
+        assert!(add_file(&mut lib, "/lib/Drums/notes.txt", &opts)
+            .unwrap()
+            .is_none());
+        assert!(add_file(&mut lib, "/lib/Drums/kit_demo.wav", &opts)
+            .unwrap()
+            .is_none());
+        assert_eq!(lib.packs[0].samples.len(), 2);
+        assert!(matches!(
+            add_file(&mut lib, "/other/hat.wav", &opts),
+            Err(LibError::NoPackForPath(_))
+        ));
+    }
+
+    #[test]
+    fn remove_file_takes_sample_out_of_its_pack() {
+        let mut lib = lib_of(vec![pack_of(
+            "drums",
+            &["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"],
+        )]);
+        let opts = LoadOptions::default();
+        add_file(&mut lib, "/lib/Drums/hat.wav", &opts).unwrap();
+        assert_eq!(lib.packs[0].meta.num_samples, Some(3));
+        let removed = remove_file(&mut lib, "/lib/Drums/hat.wav").unwrap();
+        assert_eq!(removed.path, "/lib/Drums/hat.wav");
+        assert_eq!(lib.packs[0].meta.num_samples, Some(2));
+        assert_eq!(
+            paths_of(&lib.packs[0].samples),
+            vec!["/lib/Drums/kick.wav", "/lib/Drums/snare.wav"]
+        );
+        assert!(remove_file(&mut lib, "/lib/Drums/hat.wav").is_none());
+        assert!(remove_file(&mut lib, "/lib/Drums/unknown.wav").is_none());
+    }
+
+    #[test]
+    fn pack_coverage_reports_missing_hats() {
+        let pack = pack_of(
+            "drums",
+            &[
+                "/lib/Drums/kick_01.wav",
+                "/lib/Drums/kick_02.wav",
+                "/lib/Drums/snare_01.wav",
+                "/lib/Drums/drum_loop_[124].wav",
+            ],
+        );
+        let report = pack_coverage(&pack);
+        assert_eq!(
+            report.present_categories,
+            vec![DrumCategory::Kick, DrumCategory::Snare]
+        );
+        assert!(report.missing_categories.contains(&DrumCategory::Hat));
+        assert_eq!(report.present_bands, vec![(120, 130)]);
+        assert_eq!(report.missing_bands.len(), DEFAULT_TEMPO_BANDS.len() - 1);
+    }
+
+    #[test]
+    fn tempo_sanitize_applies_each_policy() {
+        let sanitize = |policy| TempoSanitize {
+            min: 60,
+            max: 200,
+            policy,
+        };
+        let one_shot = sanitize(OutOfRangeTempo::OneShot);
+        let unknown = sanitize(OutOfRangeTempo::Unknown);
+        assert_eq!(one_shot.apply(SampleType::Loop(45)), SampleType::OneShot);
+        assert_eq!(unknown.apply(SampleType::Loop(45)), SampleType::Loop(0));
+        assert_eq!(unknown.apply(SampleType::Loop(240)), SampleType::Loop(0));
+        for policy in [one_shot, unknown] {
+            assert_eq!(policy.apply(SampleType::Loop(120)), SampleType::Loop(120));
+            assert_eq!(policy.apply(SampleType::OneShot), SampleType::OneShot);
+            // No detected tempo isn't an out of range one
+            assert_eq!(policy.apply(SampleType::Loop(0)), SampleType::Loop(0));
+        }
+
+        let opts = LoadOptions {
+            tempo_sanitize: Some(one_shot),
+            ..Default::default()
+        };
+        assert_eq!(
+            detect_type_with_options("/lib/Loops/pad_loop_45.wav", &opts),
+            SampleType::OneShot
+        );
+        assert_eq!(
+            detect_type_with_options("/lib/Loops/pad_loop.wav", &opts),
+            SampleType::Loop(0)
+        );
+        assert_eq!(
+            detect_type_with_options("/lib/Loops/pad_loop_[120].wav", &opts),
+            SampleType::Loop(120)
+        );
+    }
+
+    #[test]
+    fn reclassify_keeps_type_overrides() {
+        let mut lib = lib_of(vec![pack_of(
+            "pack",
+            &["/lib/Grooves/groove_120.wav", "/lib/Grooves/shaker.wav"],
+        )]);
+        let overrides = HashMap::from([(
+            "/lib/Grooves/groove_120.wav".to_string(),
+            SampleType::OneShot,
+        )]);
+        apply_type_overrides(&mut lib, &overrides);
+        let opts = LoadOptions {
+            loop_folders: vec!["grooves".to_string()],
+            ..Default::default()
+        };
+        assert_eq!(reclassify_if_options_changed(&mut lib, &opts), 1);
+        assert_eq!(lib.packs[0].samples[0].sampletype, SampleType::OneShot);
+        assert!(lib.packs[0].samples[0].type_overridden);
+        assert_eq!(lib.packs[0].samples[1].sampletype, SampleType::Loop(0));
+    }
 }